@@ -0,0 +1,123 @@
+use anyhow::{bail, Context, Result};
+use flate2::read::GzDecoder;
+use sevenz_rust::{Password, SevenZReader};
+use std::fs::{self, File};
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+const ROM_EXTENSIONS: [&str; 3] = ["gb", "gbc", "sgb"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Zip,
+    Gzip,
+    SevenZip,
+}
+
+impl Format {
+    fn of(path: &Path) -> Option<Format> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "zip" => Some(Format::Zip),
+            "gz" => Some(Format::Gzip),
+            "7z" => Some(Format::SevenZip),
+            _ => None,
+        }
+    }
+}
+
+/// Whether `path` names an archive that `extract_rom` can read, going by its extension.
+pub fn is_archive(path: &Path) -> bool {
+    Format::of(path).is_some()
+}
+
+/// Reads the ROM out of a .zip, .gz or .7z file. Zip and 7-Zip archives may hold
+/// other files; the first with a Game Boy ROM extension is used.
+pub fn extract_rom(path: &Path) -> Result<Vec<u8>> {
+    match Format::of(path) {
+        Some(Format::Zip) => extract_zip(&fs::read(path)?),
+        Some(Format::Gzip) => extract_gzip(&fs::read(path)?),
+        Some(Format::SevenZip) => extract_7z(File::open(path)?),
+        None => bail!("{} is not a .zip, .gz or .7z archive", path.display()),
+    }
+}
+
+fn is_rom_name(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            ROM_EXTENSIONS
+                .iter()
+                .any(|rom| extension.eq_ignore_ascii_case(rom))
+        })
+}
+
+fn extract_zip(data: &[u8]) -> Result<Vec<u8>> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(data))?;
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        if entry.is_file() && is_rom_name(entry.name()) {
+            let mut rom = Vec::new();
+            entry
+                .read_to_end(&mut rom)
+                .with_context(|| format!("Failed to decompress {}", entry.name()))?;
+            return Ok(rom);
+        }
+    }
+    bail!("the archive holds no .gb, .gbc or .sgb file")
+}
+
+// A gzip file holds a single file, so there is no name to check.
+fn extract_gzip(data: &[u8]) -> Result<Vec<u8>> {
+    let mut rom = Vec::new();
+    GzDecoder::new(data).read_to_end(&mut rom)?;
+    Ok(rom)
+}
+
+fn extract_7z(file: File) -> Result<Vec<u8>> {
+    let length = file.metadata()?.len();
+    let mut archive = SevenZReader::new(file, length, Password::empty())?;
+    let mut rom = None;
+    archive.for_each_entries(|entry, reader| {
+        if entry.is_directory() || !is_rom_name(entry.name()) {
+            // Entries in a solid block have to be read through to get to later ones.
+            std::io::copy(reader, &mut std::io::sink())?;
+            return Ok(true);
+        }
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        rom = Some(data);
+        Ok(false)
+    })?;
+    rom.context("the archive holds no .gb, .gbc or .sgb file")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    #[test]
+    fn test_extracts_the_first_rom_from_zip_and_gzip() {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file("readme.txt", SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(b"not a rom").unwrap();
+        zip.start_file("Game/GAME.GBC", SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(&[0xC3, 0x50, 0x01]).unwrap();
+        let zip = zip.finish().unwrap().into_inner();
+        assert_eq!(extract_zip(&zip).unwrap(), [0xC3, 0x50, 0x01]);
+
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(&[0x00, 0xC3]).unwrap();
+        assert_eq!(extract_gzip(&gzip.finish().unwrap()).unwrap(), [0x00, 0xC3]);
+
+        assert!(is_archive(Path::new("roms/tetris.ZIP")));
+        assert!(!is_archive(Path::new("roms/tetris.gb")));
+    }
+}
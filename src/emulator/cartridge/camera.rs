@@ -0,0 +1,204 @@
+use super::{ram_bank_offset, read_rom_bank, Mbc};
+
+/// The part of the sensor image the camera keeps, one 8-bit grey level per pixel.
+pub const IMAGE_WIDTH: usize = 128;
+pub const IMAGE_HEIGHT: usize = 112;
+pub const IMAGE_SIZE: usize = IMAGE_WIDTH * IMAGE_HEIGHT;
+
+// Setting this bit in the RAM bank register maps the sensor registers instead.
+const SELECT_REGISTERS: u8 = 0x10;
+
+const REGISTER_COUNT: usize = 0x36;
+const CONTROL: usize = 0x00;
+const EXPOSURE_HIGH: usize = 0x02;
+const EXPOSURE_LOW: usize = 0x03;
+// Three thresholds for each pixel of a 4x4 dither pattern.
+const DITHER_MATRIX: usize = 0x06;
+
+const CAPTURE: u8 = 0b0000_0001;
+
+// The exposure at which the host's grey levels are used unchanged.
+const NEUTRAL_EXPOSURE: u32 = 0x0800;
+
+// How long a capture takes, in CPU cycles at single speed.
+const CAPTURE_BASE_CYCLES: u32 = 129_792;
+const CAPTURE_CYCLES_PER_EXPOSURE: u32 = 64;
+
+// Where a captured picture is stored in RAM bank 0, as 16x14 tiles.
+const IMAGE_ADDRESS: usize = 0x0100;
+
+/// The Game Boy Camera's mapper and sensor. The picture comes from the host through
+/// `set_image`; its brightness is scaled by the exposure the game asks for and then
+/// dithered down to four shades with the game's own thresholds.
+pub struct PocketCamera {
+    ram_write_enabled: bool,
+    rom_bank: u8,
+    ram_bank: u8,
+    registers: [u8; REGISTER_COUNT],
+    image: Vec<u8>,
+    // Cycles until the capture in progress finishes.
+    capture_cycles: u32,
+}
+
+impl PocketCamera {
+    pub fn new() -> Self {
+        PocketCamera {
+            ram_write_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            registers: [0; REGISTER_COUNT],
+            image: vec![0x80; IMAGE_SIZE],
+            capture_cycles: 0,
+        }
+    }
+
+    fn exposure(&self) -> u32 {
+        u16::from_be_bytes([self.registers[EXPOSURE_HIGH], self.registers[EXPOSURE_LOW]]) as u32
+    }
+
+    // Develops the current image into RAM as 2 bits per pixel tile data, returning
+    // whether RAM was large enough to hold it.
+    fn capture(&mut self, ram: &mut [u8]) -> bool {
+        if ram.len() < IMAGE_ADDRESS + IMAGE_SIZE / 4 {
+            return false;
+        }
+        let exposure = self.exposure();
+        for y in 0..IMAGE_HEIGHT {
+            for x in 0..IMAGE_WIDTH {
+                let level = (self.image[y * IMAGE_WIDTH + x] as u32 * exposure / NEUTRAL_EXPOSURE)
+                    .min(0xFF) as u8;
+                let matrix = DITHER_MATRIX + ((y % 4) * 4 + (x % 4)) * 3;
+                let thresholds = &self.registers[matrix..matrix + 3];
+                let shade = thresholds
+                    .iter()
+                    .filter(|&&threshold| level < threshold)
+                    .count() as u8;
+
+                let tile = (y / 8) * (IMAGE_WIDTH / 8) + x / 8;
+                let offset = IMAGE_ADDRESS + tile * 16 + (y % 8) * 2;
+                let bit = 0x80 >> (x % 8);
+                for (plane, mask) in [(0, 0b01), (1, 0b10)] {
+                    if shade & mask != 0 {
+                        ram[offset + plane] |= bit;
+                    } else {
+                        ram[offset + plane] &= !bit;
+                    }
+                }
+            }
+        }
+        self.capture_cycles = CAPTURE_BASE_CYCLES + exposure * CAPTURE_CYCLES_PER_EXPOSURE;
+        true
+    }
+}
+
+impl Mbc for PocketCamera {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        read_rom_bank(rom, self.rom_bank(address), address)
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) -> bool {
+        match address {
+            0x0000..=0x1FFF => self.ram_write_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = value & 0x3F,
+            0x4000..=0x5FFF => self.ram_bank = value & 0x1F,
+            _ => {}
+        }
+        false
+    }
+
+    // RAM can always be read; only writing it needs enabling.
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        if self.ram_bank & SELECT_REGISTERS != 0 {
+            return match (address & 0x7F) as usize {
+                // Only the control register reads back, with the capture bit set
+                // while the sensor is busy.
+                CONTROL => {
+                    let busy = if self.capture_cycles > 0 { CAPTURE } else { 0 };
+                    (self.registers[CONTROL] & !CAPTURE) | busy
+                }
+                _ => 0x00,
+            };
+        }
+        ram_bank_offset(ram, self.ram_bank as usize, address).map_or(0xFF, |offset| ram[offset])
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        if self.ram_bank & SELECT_REGISTERS != 0 {
+            let register = (address & 0x7F) as usize;
+            if register < REGISTER_COUNT {
+                self.registers[register] = value;
+            }
+            // Only a capture changes RAM; the sensor registers themselves are lost
+            // when the power goes.
+            return register == CONTROL
+                && value & CAPTURE != 0
+                && self.capture_cycles == 0
+                && self.capture(ram);
+        }
+        // RAM cannot be written while a capture is being stored in it.
+        if !self.ram_write_enabled || self.capture_cycles > 0 {
+            return false;
+        }
+        let Some(offset) = ram_bank_offset(ram, self.ram_bank as usize, address) else {
+            return false;
+        };
+        ram[offset] = value;
+        true
+    }
+
+    fn rom_bank(&self, address: u16) -> usize {
+        match address {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank as usize,
+        }
+    }
+
+    fn tick(&mut self, double_speed: bool) {
+        let cycles = if double_speed { 2 } else { 4 };
+        self.capture_cycles = self.capture_cycles.saturating_sub(cycles);
+    }
+
+    fn set_image(&mut self, image: &[u8]) {
+        if image.len() == IMAGE_SIZE {
+            self.image.copy_from_slice(image);
+        }
+    }
+
+    fn requests_save(&self) -> bool {
+        !self.ram_write_enabled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture_dithers_the_host_image_into_ram() {
+        let mut ram = vec![0; 0x20000];
+        let mut mbc = PocketCamera::new();
+        let mut image = vec![0xFF; IMAGE_SIZE];
+        image[0] = 0x00;
+        image[1] = 0x60;
+        mbc.set_image(&image);
+
+        mbc.write_register(0x4000, SELECT_REGISTERS);
+        mbc.write_ram(&mut ram, 0xA000 + EXPOSURE_HIGH as u16, 0x08);
+        for register in (DITHER_MATRIX..REGISTER_COUNT).step_by(3) {
+            mbc.write_ram(&mut ram, 0xA000 + register as u16, 0x40);
+            mbc.write_ram(&mut ram, 0xA001 + register as u16, 0x80);
+            mbc.write_ram(&mut ram, 0xA002 + register as u16, 0xC0);
+        }
+        mbc.write_ram(&mut ram, 0xA000, CAPTURE);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), CAPTURE);
+
+        // Black, then dark grey, then white.
+        assert_eq!(ram[IMAGE_ADDRESS], 0b1000_0000);
+        assert_eq!(ram[IMAGE_ADDRESS + 1], 0b1100_0000);
+
+        for _ in 0..(CAPTURE_BASE_CYCLES + 0x0800 * CAPTURE_CYCLES_PER_EXPOSURE) / 4 {
+            mbc.tick(false);
+        }
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0x00);
+    }
+}
@@ -0,0 +1,408 @@
+use crate::emulator::error::CartridgeError;
+use std::fmt;
+
+const HEADER_END: usize = 0x150;
+
+const TITLE_START: usize = 0x134;
+const MANUFACTURER_CODE_START: usize = 0x13F;
+const CGB_FLAG_ADDRESS: usize = 0x143;
+const NEW_LICENSEE_ADDRESS: usize = 0x144;
+const SGB_FLAG_ADDRESS: usize = 0x146;
+const CARTRIDGE_TYPE_ADDRESS: usize = 0x147;
+const ROM_SIZE_ADDRESS: usize = 0x148;
+const RAM_SIZE_ADDRESS: usize = 0x149;
+const DESTINATION_ADDRESS: usize = 0x14A;
+const OLD_LICENSEE_ADDRESS: usize = 0x14B;
+const VERSION_ADDRESS: usize = 0x14C;
+const HEADER_CHECKSUM_ADDRESS: usize = 0x14D;
+const GLOBAL_CHECKSUM_ADDRESS: usize = 0x14E;
+
+// An old licensee code of 0x33 means the new two-character code is used instead.
+const USE_NEW_LICENSEE: u8 = 0x33;
+
+pub const ROM_BANK_SIZE: usize = 0x4000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgbSupport {
+    None,
+    // Runs on the DMG too, but uses CGB features when they are available.
+    Enhanced,
+    Only,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Licensee {
+    Old(u8),
+    New(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Destination {
+    Japan,
+    Overseas,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mapper {
+    None,
+    Mbc1,
+    Mbc2,
+    Mbc3,
+    Mbc5,
+    Mbc6,
+    Mbc7,
+    Mmm01,
+    HuC1,
+    HuC3,
+    Tama5,
+    PocketCamera,
+    // A cartridge type code that no known controller uses.
+    Unsupported,
+}
+
+/// The mapper and extra hardware on the cartridge board, decoded from 0x0147.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CartridgeType {
+    pub code: u8,
+    pub mapper: Mapper,
+    pub ram: bool,
+    pub battery: bool,
+    pub timer: bool,
+    pub rumble: bool,
+}
+
+impl CartridgeType {
+    fn from_code(code: u8) -> Self {
+        let (mapper, ram, battery, timer, rumble) = match code {
+            0x00 => (Mapper::None, false, false, false, false),
+            0x01 => (Mapper::Mbc1, false, false, false, false),
+            0x02 => (Mapper::Mbc1, true, false, false, false),
+            0x03 => (Mapper::Mbc1, true, true, false, false),
+            0x05 => (Mapper::Mbc2, false, false, false, false),
+            0x06 => (Mapper::Mbc2, false, true, false, false),
+            0x08 => (Mapper::None, true, false, false, false),
+            0x09 => (Mapper::None, true, true, false, false),
+            0x0B => (Mapper::Mmm01, false, false, false, false),
+            0x0C => (Mapper::Mmm01, true, false, false, false),
+            0x0D => (Mapper::Mmm01, true, true, false, false),
+            0x0F => (Mapper::Mbc3, false, true, true, false),
+            0x10 => (Mapper::Mbc3, true, true, true, false),
+            0x11 => (Mapper::Mbc3, false, false, false, false),
+            0x12 => (Mapper::Mbc3, true, false, false, false),
+            0x13 => (Mapper::Mbc3, true, true, false, false),
+            0x19 => (Mapper::Mbc5, false, false, false, false),
+            0x1A => (Mapper::Mbc5, true, false, false, false),
+            0x1B => (Mapper::Mbc5, true, true, false, false),
+            0x1C => (Mapper::Mbc5, false, false, false, true),
+            0x1D => (Mapper::Mbc5, true, false, false, true),
+            0x1E => (Mapper::Mbc5, true, true, false, true),
+            0x20 => (Mapper::Mbc6, true, true, false, false),
+            0x22 => (Mapper::Mbc7, true, true, false, false),
+            0xFC => (Mapper::PocketCamera, true, true, false, false),
+            0xFD => (Mapper::Tama5, true, true, true, false),
+            0xFE => (Mapper::HuC3, true, true, true, false),
+            0xFF => (Mapper::HuC1, true, true, false, false),
+            _ => (Mapper::Unsupported, false, false, false, false),
+        };
+        CartridgeType {
+            code,
+            mapper,
+            ram,
+            battery,
+            timer,
+            rumble,
+        }
+    }
+}
+
+/// The cartridge header at 0x0100-0x014F, which describes the game and the hardware
+/// on its board.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CartridgeHeader {
+    pub title: String,
+    pub manufacturer_code: Option<String>,
+    pub cgb_support: CgbSupport,
+    pub licensee: Licensee,
+    pub sgb_support: bool,
+    pub cartridge_type: CartridgeType,
+    pub rom_size: usize,
+    pub ram_size: usize,
+    pub destination: Destination,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+    // A real boot ROM locks up on a wrong header checksum, but ROM hacks often leave
+    // it stale, so it is only reported, like the global checksum, which nothing checks.
+    pub header_checksum_valid: bool,
+    pub global_checksum_valid: bool,
+}
+
+impl CartridgeHeader {
+    pub fn parse(rom: &[u8]) -> Result<Self, CartridgeError> {
+        if rom.len() < HEADER_END {
+            return Err(CartridgeError::Truncated { length: rom.len() });
+        }
+        let whole_rom = rom;
+        let rom = mmm01_menu(rom).unwrap_or(rom);
+
+        let cgb_support = match rom[CGB_FLAG_ADDRESS] {
+            0xC0 => CgbSupport::Only,
+            flag if flag & 0x80 != 0 => CgbSupport::Enhanced,
+            _ => CgbSupport::None,
+        };
+        // CGB-era games shortened the title to make room for a manufacturer code,
+        // but plenty of them still use all 15 characters for the title instead.
+        let code = &rom[MANUFACTURER_CODE_START..CGB_FLAG_ADDRESS];
+        let has_manufacturer_code = cgb_support != CgbSupport::None
+            && code
+                .iter()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit());
+        let (title_end, manufacturer_code) = if has_manufacturer_code {
+            (MANUFACTURER_CODE_START, Some(ascii(code)))
+        } else if cgb_support != CgbSupport::None {
+            (CGB_FLAG_ADDRESS, None)
+        } else {
+            (NEW_LICENSEE_ADDRESS, None)
+        };
+
+        let licensee = match rom[OLD_LICENSEE_ADDRESS] {
+            USE_NEW_LICENSEE => Licensee::New(ascii(&rom[NEW_LICENSEE_ADDRESS..SGB_FLAG_ADDRESS])),
+            code => Licensee::Old(code),
+        };
+
+        let header_checksum = rom[HEADER_CHECKSUM_ADDRESS];
+        let global_checksum = u16::from_be_bytes([
+            rom[GLOBAL_CHECKSUM_ADDRESS],
+            rom[GLOBAL_CHECKSUM_ADDRESS + 1],
+        ]);
+
+        Ok(CartridgeHeader {
+            title: ascii(&rom[TITLE_START..title_end]),
+            manufacturer_code,
+            cgb_support,
+            licensee,
+            sgb_support: rom[SGB_FLAG_ADDRESS] == 0x03,
+            cartridge_type: CartridgeType::from_code(rom[CARTRIDGE_TYPE_ADDRESS]),
+            rom_size: rom_size(rom[ROM_SIZE_ADDRESS])?,
+            ram_size: ram_size(rom[RAM_SIZE_ADDRESS])?,
+            destination: match rom[DESTINATION_ADDRESS] {
+                0x00 => Destination::Japan,
+                _ => Destination::Overseas,
+            },
+            version: rom[VERSION_ADDRESS],
+            header_checksum,
+            global_checksum,
+            header_checksum_valid: header_checksum_of(rom) == header_checksum,
+            global_checksum_valid: global_checksum_of(whole_rom) == global_checksum,
+        })
+    }
+}
+
+impl fmt::Display for CartridgeHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Title:          {}", self.title)?;
+        if let Some(code) = &self.manufacturer_code {
+            writeln!(f, "Manufacturer:   {}", code)?;
+        }
+        match &self.licensee {
+            Licensee::Old(code) => writeln!(f, "Licensee:       {:02X}", code)?,
+            Licensee::New(code) => writeln!(f, "Licensee:       {}", code)?,
+        }
+        writeln!(
+            f,
+            "Type:           {:02X} ({:?}{}{}{}{})",
+            self.cartridge_type.code,
+            self.cartridge_type.mapper,
+            if self.cartridge_type.ram { "+RAM" } else { "" },
+            if self.cartridge_type.battery {
+                "+BATTERY"
+            } else {
+                ""
+            },
+            if self.cartridge_type.timer {
+                "+TIMER"
+            } else {
+                ""
+            },
+            if self.cartridge_type.rumble {
+                "+RUMBLE"
+            } else {
+                ""
+            },
+        )?;
+        writeln!(f, "ROM size:       {} KiB", self.rom_size / 1024)?;
+        writeln!(f, "RAM size:       {} KiB", self.ram_size / 1024)?;
+        writeln!(f, "CGB support:    {:?}", self.cgb_support)?;
+        writeln!(f, "SGB support:    {}", self.sgb_support)?;
+        writeln!(f, "Destination:    {:?}", self.destination)?;
+        writeln!(f, "Version:        {}", self.version)?;
+        writeln!(
+            f,
+            "Header sum:     {:02X}{}",
+            self.header_checksum,
+            if self.header_checksum_valid {
+                ""
+            } else {
+                " (mismatch)"
+            }
+        )?;
+        write!(
+            f,
+            "Global sum:     {:04X}{}",
+            self.global_checksum,
+            if self.global_checksum_valid {
+                ""
+            } else {
+                " (mismatch)"
+            }
+        )
+    }
+}
+
+// MMM01 multicarts boot into a menu in the last 32 KiB of the ROM, and it is the
+// menu's header that describes the cartridge, while bank 0 holds the first game's.
+// The menu header only counts if its checksum is right, as random data at the end
+// of an ordinary ROM could otherwise pass for one.
+fn mmm01_menu(rom: &[u8]) -> Option<&[u8]> {
+    let menu = &rom[rom.len().checked_sub(2 * ROM_BANK_SIZE)?..];
+    let is_mmm01 = CartridgeType::from_code(menu[CARTRIDGE_TYPE_ADDRESS]).mapper == Mapper::Mmm01;
+    (is_mmm01 && header_checksum_of(menu) == menu[HEADER_CHECKSUM_ADDRESS]).then_some(menu)
+}
+
+/// The checksum the boot ROM verifies over 0x0134-0x014C.
+pub fn header_checksum_of(rom: &[u8]) -> u8 {
+    rom[TITLE_START..HEADER_CHECKSUM_ADDRESS]
+        .iter()
+        .fold(0u8, |sum, byte| sum.wrapping_sub(*byte).wrapping_sub(1))
+}
+
+// The sum of every byte in the ROM except the checksum itself.
+fn global_checksum_of(rom: &[u8]) -> u16 {
+    rom.iter()
+        .enumerate()
+        .filter(|(address, _)| !matches!(address, 0x14E | 0x14F))
+        .fold(0u16, |sum, (_, byte)| sum.wrapping_add(*byte as u16))
+}
+
+fn rom_size(code: u8) -> Result<usize, CartridgeError> {
+    match code {
+        0x00..=0x08 => Ok((2 * ROM_BANK_SIZE) << code),
+        0x52 => Ok(72 * ROM_BANK_SIZE),
+        0x53 => Ok(80 * ROM_BANK_SIZE),
+        0x54 => Ok(96 * ROM_BANK_SIZE),
+        _ => Err(CartridgeError::UnknownRomSize(code)),
+    }
+}
+
+fn ram_size(code: u8) -> Result<usize, CartridgeError> {
+    match code {
+        0x00 => Ok(0),
+        // Never used by a licensed game, but listed in early documentation.
+        0x01 => Ok(0x800),
+        0x02 => Ok(0x2000),
+        0x03 => Ok(0x8000),
+        0x04 => Ok(0x20000),
+        0x05 => Ok(0x10000),
+        _ => Err(CartridgeError::UnknownRamSize(code)),
+    }
+}
+
+fn ascii(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|&&byte| byte != 0)
+        .map(|&byte| {
+            if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '?'
+            }
+        })
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom_with_header(title: &[u8], cartridge_type: u8) -> Vec<u8> {
+        let mut rom = vec![0; 2 * ROM_BANK_SIZE];
+        rom[TITLE_START..TITLE_START + title.len()].copy_from_slice(title);
+        rom[CARTRIDGE_TYPE_ADDRESS] = cartridge_type;
+        rom[HEADER_CHECKSUM_ADDRESS] = header_checksum_of(&rom);
+        rom
+    }
+
+    #[test]
+    fn test_parses_header_fields() {
+        let mut rom = rom_with_header(b"POKEMON RED", 0x13);
+        rom[OLD_LICENSEE_ADDRESS] = USE_NEW_LICENSEE;
+        rom[NEW_LICENSEE_ADDRESS..SGB_FLAG_ADDRESS].copy_from_slice(b"01");
+        rom[SGB_FLAG_ADDRESS] = 0x03;
+        rom[RAM_SIZE_ADDRESS] = 0x03;
+        rom[DESTINATION_ADDRESS] = 0x01;
+        rom[HEADER_CHECKSUM_ADDRESS] = header_checksum_of(&rom);
+
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.title, "POKEMON RED");
+        assert_eq!(header.licensee, Licensee::New("01".to_string()));
+        assert!(header.sgb_support);
+        assert_eq!(header.cartridge_type.mapper, Mapper::Mbc3);
+        assert!(header.cartridge_type.battery);
+        assert_eq!(header.rom_size, 0x8000);
+        assert_eq!(header.ram_size, 0x8000);
+        assert_eq!(header.destination, Destination::Overseas);
+        assert!(!header.global_checksum_valid);
+    }
+
+    #[test]
+    fn test_splits_manufacturer_code_from_cgb_title() {
+        let rom = rom_with_header(b"POKEMON_SLVAAXE\x80", 0x10);
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.title, "POKEMON_SLV");
+        assert_eq!(header.manufacturer_code.as_deref(), Some("AAXE"));
+        assert_eq!(header.cgb_support, CgbSupport::Enhanced);
+    }
+
+    #[test]
+    fn test_rejects_truncated_headers_and_flags_bad_checksums() {
+        assert_eq!(
+            CartridgeHeader::parse(&[0; 0x100]),
+            Err(CartridgeError::Truncated { length: 0x100 })
+        );
+
+        let mut rom = rom_with_header(b"TEST", 0x00);
+        assert!(CartridgeHeader::parse(&rom).unwrap().header_checksum_valid);
+        rom[HEADER_CHECKSUM_ADDRESS] ^= 0xFF;
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert!(!header.header_checksum_valid);
+        assert_eq!(header.title, "TEST");
+    }
+
+    #[test]
+    fn test_reads_mmm01_header_from_the_menu_at_the_end() {
+        let mut menu = rom_with_header(b"MENU", 0x0B);
+        menu[ROM_SIZE_ADDRESS] = 0x01;
+        menu[HEADER_CHECKSUM_ADDRESS] = header_checksum_of(&menu);
+        let rom = [rom_with_header(b"GAME", 0x01), menu.clone()].concat();
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.title, "MENU");
+        assert_eq!(header.cartridge_type.mapper, Mapper::Mmm01);
+        assert_eq!(header.rom_size, 0x10000);
+
+        // A menu header with a bad checksum is not trusted over bank 0.
+        menu[HEADER_CHECKSUM_ADDRESS] ^= 0xFF;
+        let rom = [rom_with_header(b"GAME", 0x01), menu].concat();
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.cartridge_type.mapper, Mapper::Mbc1);
+    }
+
+    #[test]
+    fn test_unknown_cartridge_type_still_parses() {
+        let rom = rom_with_header(b"TEST", 0x04);
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.cartridge_type.mapper, Mapper::Unsupported);
+    }
+}
@@ -0,0 +1,87 @@
+use super::{ram_bank_offset, read_rom_bank, Mbc};
+
+// Writing this to 0x0000-0x1FFF maps the infrared port over RAM.
+const IR_SELECT: u8 = 0x0E;
+
+// Bits 6-7 of the infrared register read as set. Bit 0 is set while light is seen.
+const IR_OPEN_BUS: u8 = 0xC0;
+
+/// Hudson's HuC1. Much like MBC1, but 0x0000-0x1FFF switches 0xA000-0xBFFF between
+/// RAM and the infrared LED and sensor.
+pub struct HuC1 {
+    ir_selected: bool,
+    rom_bank: u8,
+    ram_bank: u8,
+}
+
+impl HuC1 {
+    pub fn new() -> Self {
+        HuC1 {
+            ir_selected: false,
+            rom_bank: 1,
+            ram_bank: 0,
+        }
+    }
+}
+
+impl Mbc for HuC1 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        read_rom_bank(rom, self.rom_bank(address), address)
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) -> bool {
+        match address {
+            0x0000..=0x1FFF => self.ir_selected = value & 0x0F == IR_SELECT,
+            0x2000..=0x3FFF => self.rom_bank = (value & 0x3F).max(1),
+            0x4000..=0x5FFF => self.ram_bank = value & 0x03,
+            _ => {}
+        }
+        false
+    }
+
+    // There is no link partner, so the sensor never sees any light and nothing
+    // watches the LED.
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        if self.ir_selected {
+            return IR_OPEN_BUS;
+        }
+        ram_bank_offset(ram, self.ram_bank as usize, address).map_or(0xFF, |offset| ram[offset])
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        if self.ir_selected {
+            return false;
+        }
+        let Some(offset) = ram_bank_offset(ram, self.ram_bank as usize, address) else {
+            return false;
+        };
+        ram[offset] = value;
+        true
+    }
+
+    fn rom_bank(&self, address: u16) -> usize {
+        match address {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank as usize,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_infrared_port_replaces_ram() {
+        let mut ram = vec![0; 0x8000];
+        let mut mbc = HuC1::new();
+        mbc.write_register(0x4000, 0x01);
+        mbc.write_ram(&mut ram, 0xA000, 0x12);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0x12);
+
+        mbc.write_register(0x0000, IR_SELECT);
+        mbc.write_ram(&mut ram, 0xA000, 0x01);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), IR_OPEN_BUS);
+        assert_eq!(ram[0x2000], 0x12);
+    }
+}
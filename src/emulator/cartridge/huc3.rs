@@ -0,0 +1,262 @@
+use super::rtc::{unix_time, Crystal, RtcClock};
+use super::{ram_bank_offset, read_rom_bank, Mbc};
+
+const MINUTES_PER_DAY: u16 = 24 * 60;
+
+// What 0x0000-0x1FFF maps at 0xA000-0xBFFF.
+const RAM_READ: u8 = 0x00;
+const RAM_READ_WRITE: u8 = 0x0A;
+const RTC_COMMAND: u8 = 0x0B;
+const RTC_RESPONSE: u8 = 0x0C;
+const RTC_READY: u8 = 0x0D;
+const IR: u8 = 0x0E;
+
+// The commands written in the upper nibble while RTC_COMMAND is selected.
+const READ_AND_INCREMENT: u8 = 0x1;
+const WRITE: u8 = 0x2;
+const WRITE_AND_INCREMENT: u8 = 0x3;
+const SET_ADDRESS_LOW: u8 = 0x4;
+const SET_ADDRESS_HIGH: u8 = 0x5;
+const EXTENDED: u8 = 0x6;
+
+// Arguments to EXTENDED.
+const COPY_FROM_CLOCK: u8 = 0x0;
+const COPY_TO_CLOCK: u8 = 0x1;
+const STATUS: u8 = 0x2;
+
+// Where the clock is copied to and from: three nibbles of minutes into the day and
+// four of days.
+const CLOCK_NIBBLES: usize = 7;
+
+const IR_OPEN_BUS: u8 = 0xC0;
+
+// Save files end in a 64-bit Unix time, the minutes and days, and an alarm that is
+// not emulated, as SameBoy lays them out.
+const SAVE_LENGTH: usize = 17;
+
+/// Hudson's HuC3. Besides banking it has infrared and a clock that keeps minutes
+/// and days, talked to a nibble at a time through a small command protocol.
+pub struct HuC3 {
+    mode: u8,
+    rom_bank: u8,
+    ram_bank: u8,
+    crystal: Crystal,
+    seconds: u8,
+    minutes: u16,
+    days: u16,
+    // 256 nibbles of memory inside the RTC, the first seven of which hold copies of
+    // the time.
+    memory: [u8; 0x100],
+    address: u8,
+    command: u8,
+    response: u8,
+}
+
+impl HuC3 {
+    pub fn new(clock: RtcClock) -> Self {
+        HuC3 {
+            mode: RAM_READ,
+            rom_bank: 1,
+            ram_bank: 0,
+            crystal: Crystal::new(clock),
+            seconds: 0,
+            minutes: 0,
+            days: 0,
+            memory: [0; 0x100],
+            address: 0,
+            command: 0,
+            response: 0,
+        }
+    }
+
+    fn run(&mut self, seconds: u64) {
+        let seconds = self.seconds as u64 + seconds;
+        self.seconds = (seconds % 60) as u8;
+        let minutes = self.minutes as u64 + seconds / 60;
+        self.minutes = (minutes % MINUTES_PER_DAY as u64) as u16;
+        self.days = self
+            .days
+            .wrapping_add((minutes / MINUTES_PER_DAY as u64) as u16);
+    }
+
+    fn execute(&mut self, value: u8) {
+        self.command = (value >> 4) & 0x07;
+        let argument = value & 0x0F;
+        match self.command {
+            READ_AND_INCREMENT => {
+                self.response = self.memory[self.address as usize];
+                self.address = self.address.wrapping_add(1);
+            }
+            WRITE => self.memory[self.address as usize] = argument,
+            WRITE_AND_INCREMENT => {
+                self.memory[self.address as usize] = argument;
+                self.address = self.address.wrapping_add(1);
+            }
+            SET_ADDRESS_LOW => self.address = (self.address & 0xF0) | argument,
+            SET_ADDRESS_HIGH => self.address = (self.address & 0x0F) | (argument << 4),
+            EXTENDED => self.execute_extended(argument),
+            _ => {}
+        }
+    }
+
+    fn execute_extended(&mut self, argument: u8) {
+        let seconds = self.crystal.sync();
+        self.run(seconds);
+        match argument {
+            COPY_FROM_CLOCK => {
+                let time = (self.minutes as u32) | ((self.days as u32) << 12);
+                for (index, nibble) in self.memory[..CLOCK_NIBBLES].iter_mut().enumerate() {
+                    *nibble = ((time >> (index * 4)) & 0x0F) as u8;
+                }
+            }
+            COPY_TO_CLOCK => {
+                let time = self.memory[..CLOCK_NIBBLES]
+                    .iter()
+                    .enumerate()
+                    .fold(0u32, |time, (index, &nibble)| {
+                        time | ((nibble as u32) << (index * 4))
+                    });
+                self.minutes = (time & 0x0FFF) as u16 % MINUTES_PER_DAY;
+                self.days = (time >> 12) as u16;
+                self.seconds = 0;
+                self.crystal.restart_second();
+            }
+            // The clock is always ready.
+            STATUS => self.response = 0x1,
+            _ => {}
+        }
+    }
+}
+
+impl Mbc for HuC3 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        read_rom_bank(rom, self.rom_bank(address), address)
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) -> bool {
+        match address {
+            0x0000..=0x1FFF => self.mode = value & 0x0F,
+            0x2000..=0x3FFF => self.rom_bank = (value & 0x7F).max(1),
+            0x4000..=0x5FFF => self.ram_bank = value & 0x03,
+            _ => {}
+        }
+        false
+    }
+
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        match self.mode {
+            RAM_READ | RAM_READ_WRITE => ram_bank_offset(ram, self.ram_bank as usize, address)
+                .map_or(0xFF, |offset| ram[offset]),
+            RTC_RESPONSE => (self.command << 4) | self.response,
+            RTC_READY => 0x01,
+            // There is no link partner, so the sensor never sees any light.
+            IR => IR_OPEN_BUS,
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        match self.mode {
+            RAM_READ_WRITE => {
+                let Some(offset) = ram_bank_offset(ram, self.ram_bank as usize, address) else {
+                    return false;
+                };
+                ram[offset] = value;
+                true
+            }
+            RTC_COMMAND => {
+                self.execute(value);
+                // Of the commands, only setting the clock changes what is saved.
+                (value >> 4) & 0x07 == EXTENDED && value & 0x0F == COPY_TO_CLOCK
+            }
+            _ => false,
+        }
+    }
+
+    fn rom_bank(&self, address: u16) -> usize {
+        match address {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank as usize,
+        }
+    }
+
+    fn tick(&mut self, double_speed: bool) {
+        let seconds = self.crystal.tick(double_speed);
+        self.run(seconds);
+    }
+
+    fn battery_data(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(SAVE_LENGTH);
+        data.extend_from_slice(&unix_time().to_le_bytes());
+        data.extend_from_slice(&self.minutes.to_le_bytes());
+        data.extend_from_slice(&self.days.to_le_bytes());
+        data.resize(SAVE_LENGTH, 0);
+        data
+    }
+
+    fn load_battery_data(&mut self, data: &[u8]) {
+        if data.len() != SAVE_LENGTH {
+            return;
+        }
+        let timestamp = u64::from_le_bytes(data[0..8].try_into().unwrap());
+        self.minutes = u16::from_le_bytes([data[8], data[9]]) % MINUTES_PER_DAY;
+        self.days = u16::from_le_bytes([data[10], data[11]]);
+        let seconds = self.crystal.seconds_since(timestamp);
+        self.run(seconds);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(mbc: &mut HuC3, value: u8) -> u8 {
+        mbc.write_register(0x0000, RTC_COMMAND);
+        mbc.write_ram(&mut [], 0xA000, value);
+        mbc.write_register(0x0000, RTC_RESPONSE);
+        mbc.read_ram(&[], 0xA000) & 0x0F
+    }
+
+    #[test]
+    fn test_ram_is_only_writable_in_read_write_mode() {
+        let mut ram = vec![0; 0x2000];
+        let mut mbc = HuC3::new(RtcClock::Emulated);
+        mbc.write_ram(&mut ram, 0xA000, 0x12);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0x00);
+        mbc.write_register(0x0000, RAM_READ_WRITE);
+        mbc.write_ram(&mut ram, 0xA000, 0x12);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0x12);
+    }
+
+    #[test]
+    fn test_clock_is_set_and_read_through_rtc_memory() {
+        let mut mbc = HuC3::new(RtcClock::Emulated);
+        // 1439 minutes (0x59F) on day 2.
+        command(&mut mbc, 0x40);
+        command(&mut mbc, 0x50);
+        for nibble in [0xF, 0x9, 0x5, 0x2, 0x0, 0x0, 0x0] {
+            command(&mut mbc, 0x30 | nibble);
+        }
+        command(&mut mbc, 0x60 | COPY_TO_CLOCK);
+
+        mbc.run(60);
+        command(&mut mbc, 0x60 | COPY_FROM_CLOCK);
+        command(&mut mbc, 0x40);
+        let nibbles: Vec<u8> = (0..CLOCK_NIBBLES)
+            .map(|_| command(&mut mbc, 0x10))
+            .collect();
+        assert_eq!(nibbles, [0, 0, 0, 3, 0, 0, 0]);
+        assert_eq!(command(&mut mbc, 0x60 | STATUS), 1);
+
+        let mut restored = HuC3::new(RtcClock::Emulated);
+        restored.load_battery_data(&mbc.battery_data());
+        assert_eq!((restored.minutes, restored.days), (0, 3));
+
+        // A save from long ago is caught up on at once.
+        restored.run(400 * 24 * 60 * 60 + 61);
+        assert_eq!(
+            (restored.seconds, restored.minutes, restored.days),
+            (1, 1, 403)
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use super::{ram_bank_offset, read_rom_bank, Mbc};
+
+const NINTENDO_LOGO: [u8; 0x30] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+// Where the second game's logo sits in an MBC1M multicart, at the start of bank 0x10.
+const MULTICART_LOGO_ADDRESS: usize = 0x40104;
+
+/// MBC1. BANK1 holds the low five bits of the ROM bank and BANK2 two more bits, which
+/// select either the upper ROM bits or the RAM bank. In mode 1, BANK2 also applies
+/// to the 0x0000-0x3FFF window and to RAM.
+pub struct Mbc1 {
+    ram_enabled: bool,
+    bank1: u8,
+    bank2: u8,
+    mode: bool,
+    // MBC1M multicarts leave out BANK1's top bit, so BANK2 starts at bit 4.
+    bank1_bits: u8,
+}
+
+impl Mbc1 {
+    pub fn new(rom: &[u8]) -> Self {
+        let multicart = rom
+            .get(MULTICART_LOGO_ADDRESS..MULTICART_LOGO_ADDRESS + NINTENDO_LOGO.len())
+            .is_some_and(|logo| logo == NINTENDO_LOGO);
+        Mbc1 {
+            ram_enabled: false,
+            bank1: 1,
+            bank2: 0,
+            mode: false,
+            bank1_bits: if multicart { 4 } else { 5 },
+        }
+    }
+
+    fn upper_bits(&self) -> usize {
+        (self.bank2 as usize) << self.bank1_bits
+    }
+
+    fn ram_bank(&self) -> usize {
+        if self.mode {
+            self.bank2 as usize
+        } else {
+            0
+        }
+    }
+}
+
+impl Mbc for Mbc1 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        read_rom_bank(rom, self.rom_bank(address), address)
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) -> bool {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            // Bank 0 cannot be selected here; the check sees all five bits even on
+            // multicarts, where 0x10 therefore does map bank 0 of a game.
+            0x2000..=0x3FFF => self.bank1 = (value & 0x1F).max(1),
+            0x4000..=0x5FFF => self.bank2 = value & 0b11,
+            _ => self.mode = value & 0b1 != 0,
+        }
+        false
+    }
+
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        match ram_bank_offset(ram, self.ram_bank(), address) {
+            Some(offset) if self.ram_enabled => ram[offset],
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        match ram_bank_offset(ram, self.ram_bank(), address) {
+            Some(offset) if self.ram_enabled => {
+                ram[offset] = value;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn rom_bank(&self, address: u16) -> usize {
+        match address {
+            0x0000..=0x3FFF if self.mode => self.upper_bits(),
+            0x0000..=0x3FFF => 0,
+            _ => {
+                let mask = (1 << self.bank1_bits) - 1;
+                self.upper_bits() | (self.bank1 & mask) as usize
+            }
+        }
+    }
+
+    fn requests_save(&self) -> bool {
+        !self.ram_enabled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn banked_rom(banks: usize) -> Vec<u8> {
+        (0..banks)
+            .flat_map(|bank| vec![bank as u8; 0x4000])
+            .collect()
+    }
+
+    #[test]
+    fn test_bank_zero_maps_to_one_and_bank2_extends_rom_bank() {
+        let rom = banked_rom(128);
+        let mut mbc = Mbc1::new(&rom);
+        mbc.write_register(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 1);
+        mbc.write_register(0x2000, 0x20);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 1);
+
+        mbc.write_register(0x4000, 0x02);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x41);
+        assert_eq!(mbc.read_rom(&rom, 0x0000), 0);
+        mbc.write_register(0x6000, 0x01);
+        assert_eq!(mbc.read_rom(&rom, 0x0000), 0x40);
+    }
+
+    #[test]
+    fn test_ram_needs_enabling_and_banks_in_mode_1() {
+        let rom = banked_rom(4);
+        let mut ram = vec![0; 0x8000];
+        let mut mbc = Mbc1::new(&rom);
+        mbc.write_ram(&mut ram, 0xA000, 0x12);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0xFF);
+
+        mbc.write_register(0x0000, 0x0A);
+        mbc.write_register(0x4000, 0x01);
+        mbc.write_ram(&mut ram, 0xA000, 0x12);
+        assert_eq!(ram[0], 0x12);
+        mbc.write_register(0x6000, 0x01);
+        mbc.write_ram(&mut ram, 0xA000, 0x34);
+        assert_eq!(ram[0x2000], 0x34);
+    }
+
+    #[test]
+    fn test_multicart_uses_four_bank1_bits() {
+        let mut rom = banked_rom(64);
+        rom[MULTICART_LOGO_ADDRESS..MULTICART_LOGO_ADDRESS + NINTENDO_LOGO.len()]
+            .copy_from_slice(&NINTENDO_LOGO);
+        let mut mbc = Mbc1::new(&rom);
+        mbc.write_register(0x4000, 0x01);
+        mbc.write_register(0x2000, 0x12);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x12);
+        mbc.write_register(0x2000, 0x10);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x10);
+    }
+}
@@ -0,0 +1,92 @@
+use super::{read_rom_bank, Mbc};
+
+/// MBC2 has 512 half-bytes of RAM built in, regardless of the header's RAM size.
+pub const RAM_SIZE: usize = 0x200;
+
+/// MBC2. Its two registers share 0x0000-0x3FFF and are told apart by address bit 8.
+pub struct Mbc2 {
+    ram_enabled: bool,
+    rom_bank: u8,
+}
+
+impl Mbc2 {
+    pub fn new() -> Self {
+        Mbc2 {
+            ram_enabled: false,
+            rom_bank: 1,
+        }
+    }
+}
+
+impl Mbc for Mbc2 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        read_rom_bank(rom, self.rom_bank(address), address)
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) -> bool {
+        match address {
+            0x0000..=0x3FFF if address & 0x0100 == 0 => self.ram_enabled = value & 0x0F == 0x0A,
+            0x0000..=0x3FFF => self.rom_bank = (value & 0x0F).max(1),
+            _ => {}
+        }
+        false
+    }
+
+    // Only the low nibble of each byte exists, and the 512 of them repeat across the
+    // whole 0xA000-0xBFFF window.
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        if !self.ram_enabled || ram.is_empty() {
+            return 0xFF;
+        }
+        0xF0 | ram[address as usize % RAM_SIZE]
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        if !self.ram_enabled || ram.is_empty() {
+            return false;
+        }
+        ram[address as usize % RAM_SIZE] = value & 0x0F;
+        true
+    }
+
+    fn rom_bank(&self, address: u16) -> usize {
+        match address {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank as usize,
+        }
+    }
+
+    fn requests_save(&self) -> bool {
+        !self.ram_enabled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_address_bit_8_selects_register() {
+        let rom: Vec<u8> = (0..16).flat_map(|bank| vec![bank as u8; 0x4000]).collect();
+        let mut mbc = Mbc2::new();
+        mbc.write_register(0x2000, 0x05);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 1);
+        mbc.write_register(0x2100, 0x05);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 5);
+        mbc.write_register(0x0100, 0x00);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 1);
+    }
+
+    #[test]
+    fn test_ram_is_four_bits_wide_and_echoed() {
+        let mut ram = vec![0; RAM_SIZE];
+        let mut mbc = Mbc2::new();
+        mbc.write_register(0x0000, 0x0A);
+        mbc.write_ram(&mut ram, 0xA001, 0x5A);
+        assert_eq!(mbc.read_ram(&ram, 0xA001), 0xFA);
+        assert_eq!(mbc.read_ram(&ram, 0xBE01), 0xFA);
+
+        mbc.write_register(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(&ram, 0xA001), 0xFF);
+    }
+}
@@ -0,0 +1,167 @@
+use super::rtc::{Rtc, RtcClock};
+use super::{ram_bank_offset, read_rom_bank, Mbc};
+
+// MBC30, found in the Japanese Pokémon Crystal, has an eighth ROM bank bit and a
+// third RAM bank bit. Carts larger than 2 MiB must be using one.
+const MBC30_ROM_SIZE: usize = 0x200000;
+
+/// MBC3, with the optional real-time clock mapped in place of a RAM bank.
+pub struct Mbc3 {
+    ram_enabled: bool,
+    rom_bank: u8,
+    rom_bank_mask: u8,
+    // 0x00-0x07 select a RAM bank and 0x08-0x0C an RTC register.
+    bank_select: u8,
+    ram_bank_mask: u8,
+    rtc: Option<Rtc>,
+    // The last value written to the latch register; writing 0 then 1 latches the RTC.
+    latch_write: u8,
+}
+
+impl Mbc3 {
+    pub fn new(rom: &[u8], timer: bool, clock: RtcClock) -> Self {
+        let mbc30 = rom.len() > MBC30_ROM_SIZE;
+        Mbc3 {
+            ram_enabled: false,
+            rom_bank: 1,
+            rom_bank_mask: if mbc30 { 0xFF } else { 0x7F },
+            bank_select: 0,
+            ram_bank_mask: if mbc30 { 0x07 } else { 0x03 },
+            rtc: timer.then(|| Rtc::new(clock)),
+            latch_write: 0xFF,
+        }
+    }
+
+    // The RTC, if the selected bank is one of its registers.
+    fn selected_rtc(&self) -> Option<&Rtc> {
+        self.rtc
+            .as_ref()
+            .filter(|_| (0x08..=0x0C).contains(&self.bank_select))
+    }
+}
+
+impl Mbc for Mbc3 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        read_rom_bank(rom, self.rom_bank(address), address)
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) -> bool {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = (value & self.rom_bank_mask).max(1),
+            0x4000..=0x5FFF => self.bank_select = value,
+            _ => {
+                if self.latch_write == 0x00 && value == 0x01 {
+                    if let Some(rtc) = &mut self.rtc {
+                        rtc.latch();
+                    }
+                }
+                self.latch_write = value;
+            }
+        }
+        false
+    }
+
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        if let Some(rtc) = self.selected_rtc() {
+            return rtc.read(self.bank_select);
+        }
+        match ram_bank_offset(ram, self.bank_select as usize, address) {
+            Some(offset) if self.bank_select <= self.ram_bank_mask => ram[offset],
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        if !self.ram_enabled {
+            return false;
+        }
+        if self.selected_rtc().is_some() {
+            if let Some(rtc) = &mut self.rtc {
+                rtc.write(self.bank_select, value);
+            }
+            return true;
+        }
+        match ram_bank_offset(ram, self.bank_select as usize, address) {
+            Some(offset) if self.bank_select <= self.ram_bank_mask => {
+                ram[offset] = value;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn rom_bank(&self, address: u16) -> usize {
+        match address {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank as usize,
+        }
+    }
+
+    fn tick(&mut self, double_speed: bool) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.tick(double_speed);
+        }
+    }
+
+    fn battery_data(&self) -> Vec<u8> {
+        self.rtc.as_ref().map_or_else(Vec::new, Rtc::save)
+    }
+
+    fn load_battery_data(&mut self, data: &[u8]) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.load(data);
+        }
+    }
+
+    fn requests_save(&self) -> bool {
+        !self.ram_enabled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rom_and_ram_banks() {
+        let rom: Vec<u8> = (0..128).flat_map(|bank| vec![bank as u8; 0x4000]).collect();
+        let mut ram = vec![0; 0x8000];
+        let mut mbc = Mbc3::new(&rom, false, RtcClock::Emulated);
+        mbc.write_register(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 1);
+        mbc.write_register(0x2000, 0xFF);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x7F);
+
+        mbc.write_register(0x0000, 0x0A);
+        mbc.write_register(0x4000, 0x03);
+        mbc.write_ram(&mut ram, 0xA000, 0x12);
+        assert_eq!(ram[0x6000], 0x12);
+        mbc.write_register(0x4000, 0x08);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0xFF);
+    }
+
+    #[test]
+    fn test_rtc_registers_are_read_through_the_latch() {
+        let rom = vec![0; 0x8000];
+        let mut mbc = Mbc3::new(&rom, true, RtcClock::Emulated);
+        mbc.write_register(0x0000, 0x0A);
+        mbc.write_register(0x4000, 0x09);
+        mbc.write_ram(&mut [], 0xA000, 0x2A);
+        assert_eq!(mbc.read_ram(&[], 0xA000), 0x2A);
+
+        mbc.write_register(0x4000, 0x08);
+        for _ in 0..0x100000 {
+            mbc.tick(false);
+        }
+        assert_eq!(mbc.read_ram(&[], 0xA000), 0x00);
+        mbc.write_register(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(&[], 0xA000), 0x00);
+        mbc.write_register(0x6000, 0x00);
+        mbc.write_register(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(&[], 0xA000), 0x01);
+    }
+}
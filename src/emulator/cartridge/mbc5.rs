@@ -0,0 +1,122 @@
+use super::{ram_bank_offset, read_rom_bank, Mbc};
+
+const RUMBLE_MOTOR: u8 = 0b0000_1000;
+
+/// MBC5. Its 9-bit ROM bank number can also select bank 0, and on rumble carts the
+/// fourth RAM bank bit drives the motor instead.
+pub struct Mbc5 {
+    ram_enabled: bool,
+    rom_bank: u16,
+    ram_bank: u8,
+    has_rumble: bool,
+    rumble: bool,
+}
+
+impl Mbc5 {
+    pub fn new(has_rumble: bool) -> Self {
+        Mbc5 {
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            has_rumble,
+            rumble: false,
+        }
+    }
+}
+
+impl Mbc for Mbc5 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        read_rom_bank(rom, self.rom_bank(address), address)
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) -> bool {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value == 0x0A,
+            0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | value as u16,
+            0x3000..=0x3FFF => {
+                self.rom_bank = (self.rom_bank & 0xFF) | (((value & 0b1) as u16) << 8)
+            }
+            0x4000..=0x5FFF if self.has_rumble => {
+                self.rumble = value & RUMBLE_MOTOR != 0;
+                self.ram_bank = value & 0x07;
+            }
+            0x4000..=0x5FFF => self.ram_bank = value & 0x0F,
+            _ => {}
+        }
+        false
+    }
+
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        match ram_bank_offset(ram, self.ram_bank as usize, address) {
+            Some(offset) if self.ram_enabled => ram[offset],
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        match ram_bank_offset(ram, self.ram_bank as usize, address) {
+            Some(offset) if self.ram_enabled => {
+                ram[offset] = value;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn rom_bank(&self, address: u16) -> usize {
+        match address {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank as usize,
+        }
+    }
+
+    fn rumble(&self) -> bool {
+        self.rumble
+    }
+
+    fn requests_save(&self) -> bool {
+        !self.ram_enabled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nine_bit_rom_bank_including_bank_zero() {
+        let rom: Vec<u8> = (0..512u32)
+            .flat_map(|bank| {
+                let mut data = vec![0; 0x4000];
+                data[0] = bank as u8;
+                data[1] = (bank >> 8) as u8;
+                data
+            })
+            .collect();
+        let mut mbc = Mbc5::new(false);
+        mbc.write_register(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0);
+        mbc.write_register(0x2000, 0x23);
+        mbc.write_register(0x3000, 0x01);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x23);
+        assert_eq!(mbc.read_rom(&rom, 0x4001), 0x01);
+    }
+
+    #[test]
+    fn test_rumble_carts_use_bit_3_for_the_motor() {
+        let mut ram = vec![0; 0x20000];
+        let mut mbc = Mbc5::new(true);
+        mbc.write_register(0x0000, 0x0A);
+        mbc.write_register(0x4000, 0x0B);
+        assert!(mbc.rumble());
+        mbc.write_ram(&mut ram, 0xA000, 0x12);
+        assert_eq!(ram[0x6000], 0x12);
+
+        let mut mbc = Mbc5::new(false);
+        mbc.write_register(0x0000, 0x0A);
+        mbc.write_register(0x4000, 0x0B);
+        assert!(!mbc.rumble());
+        mbc.write_ram(&mut ram, 0xA000, 0x34);
+        assert_eq!(ram[0x16000], 0x34);
+    }
+}
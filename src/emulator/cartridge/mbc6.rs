@@ -0,0 +1,275 @@
+use super::Mbc;
+
+/// MBC6 carts carry 1 MiB of flash next to their ROM.
+pub const FLASH_SIZE: usize = 0x100000;
+
+const ROM_WINDOW_SIZE: usize = 0x2000;
+const RAM_WINDOW_SIZE: usize = 0x1000;
+
+// Erasing works on whole sectors of flash.
+const FLASH_SECTOR_SIZE: usize = 0x20000;
+
+// Writing this to a window's select register maps flash there instead of ROM.
+const SELECT_FLASH: u8 = 0x08;
+
+// What the flash chip answers with in ID mode: Macronix, MX29F008.
+const FLASH_MANUFACTURER_ID: u8 = 0xC2;
+const FLASH_DEVICE_ID: u8 = 0x81;
+
+// Flash commands are written to these addresses after the unlock sequence.
+const UNLOCK_ADDRESS_1: usize = 0x5555;
+const UNLOCK_ADDRESS_2: usize = 0x2AAA;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FlashState {
+    Ready,
+    // The first and second bytes of the unlock sequence were written.
+    Unlock1,
+    Unlock2,
+    // The next write is programmed into the flash.
+    Program,
+    // An erase was started, and needs unlocking again before it is carried out.
+    EraseSetup,
+    EraseUnlock1,
+    EraseUnlock2,
+}
+
+/// MBC6, used by Net de Get. It splits 0x4000-0x7FFF and 0xA000-0xBFFF into two
+/// independently banked halves, each of which can show ROM or the flash.
+pub struct Mbc6 {
+    ram_enabled: bool,
+    ram_banks: [u8; 2],
+    rom_banks: [u8; 2],
+    flash_selected: [bool; 2],
+    flash_enabled: bool,
+    flash_write_enabled: bool,
+    flash: Vec<u8>,
+    flash_state: FlashState,
+    flash_id_mode: bool,
+}
+
+impl Mbc6 {
+    pub fn new() -> Self {
+        Mbc6 {
+            ram_enabled: false,
+            ram_banks: [0, 1],
+            rom_banks: [2, 3],
+            flash_selected: [false; 2],
+            flash_enabled: false,
+            flash_write_enabled: false,
+            // Erased flash reads as all bits set.
+            flash: vec![0xFF; FLASH_SIZE],
+            flash_state: FlashState::Ready,
+            flash_id_mode: false,
+        }
+    }
+
+    // Which half of 0x4000-0x7FFF or 0xA000-0xBFFF an address is in.
+    fn window(address: u16, size: usize) -> usize {
+        (address as usize / size) % 2
+    }
+
+    fn flash_address(&self, window: usize, address: u16) -> usize {
+        (self.rom_banks[window] as usize * ROM_WINDOW_SIZE + (address as usize % ROM_WINDOW_SIZE))
+            % FLASH_SIZE
+    }
+
+    fn ram_offset(&self, ram: &[u8], address: u16) -> Option<usize> {
+        if ram.is_empty() {
+            return None;
+        }
+        let bank = self.ram_banks[Self::window(address, RAM_WINDOW_SIZE)] as usize;
+        Some((bank * RAM_WINDOW_SIZE + (address as usize % RAM_WINDOW_SIZE)) % ram.len())
+    }
+
+    // Steps the flash command state machine, returning whether the flash changed.
+    fn write_flash(&mut self, address: usize, value: u8) -> bool {
+        let command_address = address & 0x7FFF;
+        let mut changed = false;
+        self.flash_state = match (self.flash_state, command_address, value) {
+            (FlashState::Program, _, _) => {
+                if self.flash_write_enabled {
+                    // Programming can only clear bits; setting them takes an erase.
+                    self.flash[address] &= value;
+                    changed = true;
+                }
+                FlashState::Ready
+            }
+            (_, _, 0xF0) => {
+                self.flash_id_mode = false;
+                FlashState::Ready
+            }
+            (FlashState::Ready, UNLOCK_ADDRESS_1, 0xAA) => FlashState::Unlock1,
+            (FlashState::Unlock1, UNLOCK_ADDRESS_2, 0x55) => FlashState::Unlock2,
+            (FlashState::Unlock2, UNLOCK_ADDRESS_1, 0xA0) => FlashState::Program,
+            (FlashState::Unlock2, UNLOCK_ADDRESS_1, 0x80) => FlashState::EraseSetup,
+            (FlashState::Unlock2, UNLOCK_ADDRESS_1, 0x90) => {
+                self.flash_id_mode = true;
+                FlashState::Ready
+            }
+            (FlashState::EraseSetup, UNLOCK_ADDRESS_1, 0xAA) => FlashState::EraseUnlock1,
+            (FlashState::EraseUnlock1, UNLOCK_ADDRESS_2, 0x55) => FlashState::EraseUnlock2,
+            (FlashState::EraseUnlock2, _, 0x30) => {
+                if self.flash_write_enabled {
+                    let start = address - address % FLASH_SECTOR_SIZE;
+                    self.flash[start..start + FLASH_SECTOR_SIZE].fill(0xFF);
+                    changed = true;
+                }
+                FlashState::Ready
+            }
+            (FlashState::EraseUnlock2, UNLOCK_ADDRESS_1, 0x10) => {
+                if self.flash_write_enabled {
+                    self.flash.fill(0xFF);
+                    changed = true;
+                }
+                FlashState::Ready
+            }
+            _ => FlashState::Ready,
+        };
+        changed
+    }
+}
+
+impl Mbc for Mbc6 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        if address < 0x4000 {
+            return rom.get(address as usize).copied().unwrap_or(0xFF);
+        }
+        let window = Self::window(address, ROM_WINDOW_SIZE);
+        if !self.flash_selected[window] {
+            if rom.is_empty() {
+                return 0xFF;
+            }
+            let bank = self.rom_banks[window] as usize;
+            return rom
+                [(bank * ROM_WINDOW_SIZE + (address as usize % ROM_WINDOW_SIZE)) % rom.len()];
+        }
+        if !self.flash_enabled {
+            return 0xFF;
+        }
+        let flash_address = self.flash_address(window, address);
+        if self.flash_id_mode {
+            return match flash_address & 0x01 {
+                0 => FLASH_MANUFACTURER_ID,
+                _ => FLASH_DEVICE_ID,
+            };
+        }
+        self.flash[flash_address]
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) -> bool {
+        match address {
+            0x0000..=0x03FF => self.ram_enabled = value == 0x0A,
+            0x0400..=0x07FF => self.ram_banks[0] = value & 0x07,
+            0x0800..=0x0BFF => self.ram_banks[1] = value & 0x07,
+            0x0C00..=0x0FFF => self.flash_enabled = value & 0b1 != 0,
+            0x1000..=0x1FFF => self.flash_write_enabled = value & 0b1 != 0,
+            0x2000..=0x27FF => self.rom_banks[0] = value & 0x7F,
+            0x2800..=0x2FFF => self.flash_selected[0] = value == SELECT_FLASH,
+            0x3000..=0x37FF => self.rom_banks[1] = value & 0x7F,
+            0x3800..=0x3FFF => self.flash_selected[1] = value == SELECT_FLASH,
+            _ => {
+                let window = Self::window(address, ROM_WINDOW_SIZE);
+                if self.flash_selected[window] && self.flash_enabled {
+                    let flash_address = self.flash_address(window, address);
+                    return self.write_flash(flash_address, value);
+                }
+            }
+        }
+        false
+    }
+
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        match self.ram_offset(ram, address) {
+            Some(offset) if self.ram_enabled => ram[offset],
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        match self.ram_offset(ram, address) {
+            Some(offset) if self.ram_enabled => {
+                ram[offset] = value;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // In 8 KiB banks, as that is how MBC6 switches them.
+    fn rom_bank(&self, address: u16) -> usize {
+        match address {
+            0x0000..=0x3FFF => address as usize / ROM_WINDOW_SIZE,
+            _ => self.rom_banks[Self::window(address, ROM_WINDOW_SIZE)] as usize,
+        }
+    }
+
+    fn battery_data(&self) -> Vec<u8> {
+        self.flash.clone()
+    }
+
+    fn load_battery_data(&mut self, data: &[u8]) {
+        if data.len() == FLASH_SIZE {
+            self.flash.copy_from_slice(data);
+        }
+    }
+
+    // Flash is locked again once it has been programmed, just as RAM is disabled.
+    fn requests_save(&self) -> bool {
+        !self.ram_enabled && !self.flash_write_enabled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_rom_and_ram_windows() {
+        let rom: Vec<u8> = (0..128).flat_map(|bank| vec![bank as u8; 0x2000]).collect();
+        let mut ram = vec![0; 0x8000];
+        let mut mbc = Mbc6::new();
+        mbc.write_register(0x2000, 0x05);
+        mbc.write_register(0x3000, 0x40);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x05);
+        assert_eq!(mbc.read_rom(&rom, 0x6000), 0x40);
+
+        mbc.write_register(0x0000, 0x0A);
+        mbc.write_register(0x0800, 0x03);
+        mbc.write_ram(&mut ram, 0xB000, 0x12);
+        assert_eq!(ram[0x3000], 0x12);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0x00);
+    }
+
+    #[test]
+    fn test_flash_programs_and_erases_after_unlocking() {
+        let mut mbc = Mbc6::new();
+        mbc.write_register(0x0C00, 0x01);
+        mbc.write_register(0x1000, 0x01);
+        mbc.write_register(0x2800, SELECT_FLASH);
+        mbc.write_register(0x3800, SELECT_FLASH);
+        let command = |mbc: &mut Mbc6, value: u8| {
+            mbc.write_register(0x2000, 0x02);
+            mbc.write_register(0x3000, 0x01);
+            mbc.write_register(0x5555, 0xAA);
+            mbc.write_register(0x6AAA, 0x55);
+            mbc.write_register(0x5555, value);
+        };
+
+        command(&mut mbc, 0xA0);
+        mbc.write_register(0x2000, 0x10);
+        mbc.write_register(0x4001, 0x5A);
+        assert_eq!(mbc.read_rom(&[], 0x4001), 0x5A);
+
+        command(&mut mbc, 0x90);
+        assert_eq!(mbc.read_rom(&[], 0x4000), FLASH_MANUFACTURER_ID);
+        mbc.write_register(0x4000, 0xF0);
+
+        command(&mut mbc, 0x80);
+        mbc.write_register(0x5555, 0xAA);
+        mbc.write_register(0x6AAA, 0x55);
+        mbc.write_register(0x2000, 0x10);
+        mbc.write_register(0x4000, 0x30);
+        assert_eq!(mbc.read_rom(&[], 0x4001), 0xFF);
+    }
+}
@@ -0,0 +1,352 @@
+use super::{read_rom_bank, Mbc};
+
+/// The 93LC56 EEPROM on MBC7 carts: 128 16-bit words, stored most significant byte
+/// first in place of cartridge RAM.
+pub const EEPROM_SIZE: usize = 0x100;
+
+// What the accelerometer reads when level, and how far it moves for 1 g of tilt.
+const ACCELEROMETER_CENTRE: f32 = 0x81D0 as f32;
+const ACCELEROMETER_G: f32 = 0x70 as f32;
+// The value the latched readings are reset to before taking new ones.
+const ACCELEROMETER_ERASED: u16 = 0x8000;
+
+const EEPROM_CS: u8 = 0b1000_0000;
+const EEPROM_CLK: u8 = 0b0100_0000;
+const EEPROM_DI: u8 = 0b0000_0010;
+const EEPROM_DO: u8 = 0b0000_0001;
+
+// An opcode and an 8-bit address follow the start bit.
+const COMMAND_BITS: u8 = 10;
+const WORD_BITS: u8 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Eeprom {
+    // Waiting for a start bit.
+    Idle,
+    Command,
+    // Shifting out the word at `address`, one bit per clock, moving on to the next
+    // word when it is done.
+    Read { address: u8, bit: u8 },
+    // Shifting in a word to write to `address`, or to every word.
+    Write { address: u8 },
+    WriteAll,
+}
+
+/// MBC7, with an accelerometer and a serial EEPROM in place of RAM. Both need the two
+/// RAM enables before they show up at 0xA000-0xAFFF.
+pub struct Mbc7 {
+    ram_enabled: [bool; 2],
+    rom_bank: u8,
+    tilt: (f32, f32),
+    latch_erased: bool,
+    accelerometer: (u16, u16),
+    eeprom: Eeprom,
+    eeprom_pins: u8,
+    eeprom_input: u16,
+    eeprom_input_bits: u8,
+    eeprom_write_enabled: bool,
+}
+
+impl Mbc7 {
+    pub fn new() -> Self {
+        Mbc7 {
+            ram_enabled: [false; 2],
+            rom_bank: 1,
+            tilt: (0.0, 0.0),
+            latch_erased: false,
+            accelerometer: (ACCELEROMETER_ERASED, ACCELEROMETER_ERASED),
+            eeprom: Eeprom::Idle,
+            eeprom_pins: EEPROM_DO,
+            eeprom_input: 0,
+            eeprom_input_bits: 0,
+            eeprom_write_enabled: false,
+        }
+    }
+
+    fn read_word(eeprom: &[u8], address: u8) -> u16 {
+        let offset = (address as usize * 2) % eeprom.len();
+        u16::from_be_bytes([eeprom[offset], eeprom[offset + 1]])
+    }
+
+    fn write_word(eeprom: &mut [u8], address: u8, word: u16) {
+        let offset = (address as usize * 2) % eeprom.len();
+        eeprom[offset..offset + 2].copy_from_slice(&word.to_be_bytes());
+    }
+
+    fn latch(&mut self) {
+        let axis = |g: f32| (ACCELEROMETER_CENTRE + g * ACCELEROMETER_G).clamp(0.0, 65535.0) as u16;
+        self.accelerometer = (axis(self.tilt.0), axis(self.tilt.1));
+    }
+
+    // Drives the EEPROM's pins, returning whether a command changed its contents.
+    fn write_eeprom_pins(&mut self, eeprom: &mut [u8], value: u8) -> bool {
+        let rising_edge = self.eeprom_pins & EEPROM_CLK == 0 && value & EEPROM_CLK != 0;
+        self.eeprom_pins =
+            (value & (EEPROM_CS | EEPROM_CLK | EEPROM_DI)) | (self.eeprom_pins & EEPROM_DO);
+        if value & EEPROM_CS == 0 {
+            self.eeprom = Eeprom::Idle;
+            self.eeprom_pins |= EEPROM_DO;
+            return false;
+        }
+        rising_edge && self.clock_eeprom(eeprom, value & EEPROM_DI != 0)
+    }
+
+    fn clock_eeprom(&mut self, eeprom: &mut [u8], bit: bool) -> bool {
+        match self.eeprom {
+            Eeprom::Idle => {
+                if bit {
+                    self.eeprom = Eeprom::Command;
+                    self.eeprom_input = 0;
+                    self.eeprom_input_bits = 0;
+                }
+                false
+            }
+            Eeprom::Command => self.shift_in(bit, COMMAND_BITS) && self.execute_eeprom(eeprom),
+            Eeprom::Read { address, bit } => {
+                let word = Self::read_word(eeprom, address);
+                self.set_eeprom_do(word & (0x8000 >> bit) != 0);
+                self.eeprom = if bit + 1 == WORD_BITS {
+                    Eeprom::Read {
+                        address: (address + 1) & 0x7F,
+                        bit: 0,
+                    }
+                } else {
+                    Eeprom::Read {
+                        address,
+                        bit: bit + 1,
+                    }
+                };
+                false
+            }
+            Eeprom::Write { address } => {
+                if !self.shift_in(bit, WORD_BITS) {
+                    return false;
+                }
+                let changed = self.eeprom_write_enabled;
+                if changed {
+                    Self::write_word(eeprom, address, self.eeprom_input);
+                }
+                self.finish_eeprom();
+                changed
+            }
+            Eeprom::WriteAll => {
+                if !self.shift_in(bit, WORD_BITS) {
+                    return false;
+                }
+                let changed = self.eeprom_write_enabled;
+                if changed {
+                    for address in 0..(eeprom.len() / 2) as u8 {
+                        Self::write_word(eeprom, address, self.eeprom_input);
+                    }
+                }
+                self.finish_eeprom();
+                changed
+            }
+        }
+    }
+
+    // Shifts a bit into the input register, returning whether it now holds `length`.
+    fn shift_in(&mut self, bit: bool, length: u8) -> bool {
+        self.eeprom_input = (self.eeprom_input << 1) | bit as u16;
+        self.eeprom_input_bits += 1;
+        self.eeprom_input_bits == length
+    }
+
+    fn execute_eeprom(&mut self, eeprom: &mut [u8]) -> bool {
+        let opcode = (self.eeprom_input >> 8) & 0b11;
+        let address = self.eeprom_input as u8;
+        self.eeprom_input = 0;
+        self.eeprom_input_bits = 0;
+        let changed = match opcode {
+            0b10 => {
+                // A dummy 0 comes out before the data.
+                self.set_eeprom_do(false);
+                self.eeprom = Eeprom::Read {
+                    address: address & 0x7F,
+                    bit: 0,
+                };
+                return false;
+            }
+            0b01 => {
+                self.eeprom = Eeprom::Write {
+                    address: address & 0x7F,
+                };
+                return false;
+            }
+            0b11 if self.eeprom_write_enabled => {
+                Self::write_word(eeprom, address & 0x7F, 0xFFFF);
+                true
+            }
+            0b11 => false,
+            // Opcode 0 takes its actual command from the top two address bits.
+            _ => match address >> 6 {
+                0b11 => {
+                    self.eeprom_write_enabled = true;
+                    false
+                }
+                0b00 => {
+                    self.eeprom_write_enabled = false;
+                    false
+                }
+                0b10 if self.eeprom_write_enabled => {
+                    eeprom.fill(0xFF);
+                    true
+                }
+                0b10 => false,
+                _ => {
+                    self.eeprom = Eeprom::WriteAll;
+                    return false;
+                }
+            },
+        };
+        self.finish_eeprom();
+        changed
+    }
+
+    fn finish_eeprom(&mut self) {
+        self.eeprom = Eeprom::Idle;
+        self.set_eeprom_do(true);
+    }
+
+    fn set_eeprom_do(&mut self, high: bool) {
+        if high {
+            self.eeprom_pins |= EEPROM_DO;
+        } else {
+            self.eeprom_pins &= !EEPROM_DO;
+        }
+    }
+}
+
+impl Mbc for Mbc7 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        read_rom_bank(rom, self.rom_bank(address), address)
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) -> bool {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled[0] = value == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = value & 0x7F,
+            0x4000..=0x5FFF => self.ram_enabled[1] = value == 0x40,
+            _ => {}
+        }
+        false
+    }
+
+    fn read_ram(&self, _ram: &[u8], address: u16) -> u8 {
+        if self.ram_enabled != [true, true] || address >= 0xB000 {
+            return 0xFF;
+        }
+        let (x, y) = self.accelerometer;
+        match (address >> 4) & 0x0F {
+            0x2 => x as u8,
+            0x3 => (x >> 8) as u8,
+            0x4 => y as u8,
+            0x5 => (y >> 8) as u8,
+            // The sensor has no Z axis.
+            0x6 => 0x00,
+            0x8 => self.eeprom_pins,
+            _ => 0xFF,
+        }
+    }
+
+    // Only the EEPROM is battery-backed: latching the accelerometer changes nothing
+    // that is saved.
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        if self.ram_enabled != [true, true] || address >= 0xB000 {
+            return false;
+        }
+        match (address >> 4) & 0x0F {
+            0x0 if value == 0x55 => {
+                self.accelerometer = (ACCELEROMETER_ERASED, ACCELEROMETER_ERASED);
+                self.latch_erased = true;
+                false
+            }
+            0x1 if value == 0xAA && self.latch_erased => {
+                self.latch();
+                self.latch_erased = false;
+                false
+            }
+            0x8 if !ram.is_empty() => self.write_eeprom_pins(ram, value),
+            _ => false,
+        }
+    }
+
+    fn rom_bank(&self, address: u16) -> usize {
+        match address {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank as usize,
+        }
+    }
+
+    fn set_tilt(&mut self, x: f32, y: f32) {
+        self.tilt = (x, y);
+    }
+
+    fn requests_save(&self) -> bool {
+        self.ram_enabled != [true, true]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enabled() -> Mbc7 {
+        let mut mbc = Mbc7::new();
+        mbc.write_register(0x0000, 0x0A);
+        mbc.write_register(0x4000, 0x40);
+        mbc
+    }
+
+    // Clocks bits into the EEPROM, returning what DO read after each one.
+    fn clock(mbc: &mut Mbc7, eeprom: &mut [u8], bits: &[u8]) -> Vec<u8> {
+        bits.iter()
+            .map(|&bit| {
+                let di = if bit != 0 { EEPROM_DI } else { 0 };
+                mbc.write_ram(eeprom, 0xA080, EEPROM_CS | di);
+                mbc.write_ram(eeprom, 0xA080, EEPROM_CS | EEPROM_CLK | di);
+                mbc.read_ram(eeprom, 0xA080) & EEPROM_DO
+            })
+            .collect()
+    }
+
+    fn bits(value: u32, length: u32) -> Vec<u8> {
+        (0..length)
+            .rev()
+            .map(|bit| (value >> bit) as u8 & 1)
+            .collect()
+    }
+
+    #[test]
+    fn test_accelerometer_latches_the_host_tilt() {
+        let mut mbc = enabled();
+        mbc.set_tilt(1.0, -0.5);
+        mbc.write_ram(&mut [], 0xA010, 0xAA);
+        assert_eq!(mbc.read_ram(&[], 0xA020), 0x00);
+        assert_eq!(mbc.read_ram(&[], 0xA030), 0x80);
+
+        mbc.write_ram(&mut [], 0xA000, 0x55);
+        mbc.write_ram(&mut [], 0xA010, 0xAA);
+        assert_eq!(mbc.read_ram(&[], 0xA020), 0x40);
+        assert_eq!(mbc.read_ram(&[], 0xA030), 0x82);
+        assert_eq!(mbc.read_ram(&[], 0xA040), 0x98);
+        assert_eq!(mbc.read_ram(&[], 0xA050), 0x81);
+    }
+
+    #[test]
+    fn test_eeprom_writes_need_enabling_and_read_back() {
+        let mut mbc = enabled();
+        let mut eeprom = vec![0; EEPROM_SIZE];
+        let write = [bits(0b101_0000_0011, 11), bits(0xBEEF, 16)].concat();
+        clock(&mut mbc, &mut eeprom, &write);
+        assert_eq!(eeprom[6..8], [0, 0]);
+
+        clock(&mut mbc, &mut eeprom, &bits(0b100_1100_0000, 11));
+        clock(&mut mbc, &mut eeprom, &write);
+        assert_eq!(eeprom[6..8], [0xBE, 0xEF]);
+
+        let read = clock(&mut mbc, &mut eeprom, &bits(0b110_0000_0011 << 16, 27));
+        assert_eq!(read[10], 0);
+        assert_eq!(read[11..], bits(0xBEEF, 16));
+    }
+}
@@ -0,0 +1,183 @@
+use super::{ram_bank_offset, read_rom_bank, Mbc};
+
+// Until a game is mapped, every bank bit above A14 reads as set, so the menu has
+// to sit in the last 32 KiB of the ROM.
+const MENU_BANK: usize = 0x1FE;
+
+/// MMM01, used by multi-game carts. It boots into a menu at the end of the ROM,
+/// which sets up the outer bank bits and size masks for the chosen game and then
+/// locks them, leaving an MBC1 that only sees that game.
+pub struct Mmm01 {
+    ram_enabled: bool,
+    // Once set, only the bits the game itself may change stay writable.
+    mapped: bool,
+    rom_bank_low: u8,
+    rom_bank_mid: u8,
+    rom_bank_high: u8,
+    // Bits 1-4 of BANK1 that are frozen once the game is mapped, and so come from the
+    // menu's choice of game for both windows.
+    rom_bank_mask: u8,
+    ram_bank_low: u8,
+    ram_bank_high: u8,
+    ram_bank_mask: u8,
+    mode: bool,
+    mode_locked: bool,
+    // Swaps the roles of the middle ROM bank bits and the low RAM bank bits.
+    multiplex: bool,
+}
+
+impl Mmm01 {
+    pub fn new() -> Self {
+        Mmm01 {
+            ram_enabled: false,
+            mapped: false,
+            rom_bank_low: 0,
+            rom_bank_mid: 0,
+            rom_bank_high: 0,
+            rom_bank_mask: 0,
+            ram_bank_low: 0,
+            ram_bank_high: 0,
+            ram_bank_mask: 0,
+            mode: false,
+            mode_locked: false,
+            multiplex: false,
+        }
+    }
+
+    fn frozen_rom_bits(&self) -> u8 {
+        (self.rom_bank_mask << 1) & 0x1F
+    }
+
+    fn ram_bank(&self) -> usize {
+        let low = if self.multiplex {
+            self.rom_bank_mid
+        } else if self.mode {
+            self.ram_bank_low
+        } else {
+            0
+        };
+        (low | (self.ram_bank_high << 2)) as usize
+    }
+}
+
+impl Mbc for Mmm01 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        read_rom_bank(rom, self.rom_bank(address), address)
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) -> bool {
+        match address {
+            0x0000..=0x1FFF => {
+                self.ram_enabled = value & 0x0F == 0x0A;
+                if !self.mapped {
+                    self.ram_bank_mask = (value >> 4) & 0b11;
+                    self.mapped = value & 0x40 != 0;
+                }
+            }
+            0x2000..=0x3FFF => {
+                if !self.mapped {
+                    self.rom_bank_mid = (value >> 5) & 0b11;
+                }
+                let frozen = if self.mapped {
+                    self.frozen_rom_bits()
+                } else {
+                    0
+                };
+                self.rom_bank_low = (self.rom_bank_low & frozen) | (value & !frozen & 0x1F);
+            }
+            0x4000..=0x5FFF => {
+                let frozen = if self.mapped { self.ram_bank_mask } else { 0 };
+                self.ram_bank_low = (self.ram_bank_low & frozen) | (value & !frozen & 0b11);
+                if !self.mapped {
+                    self.ram_bank_high = (value >> 2) & 0b11;
+                    self.rom_bank_high = (value >> 4) & 0b11;
+                    self.mode_locked = value & 0x40 != 0;
+                }
+            }
+            _ => {
+                if !self.mode_locked {
+                    self.mode = value & 0b1 != 0;
+                }
+                if !self.mapped {
+                    self.rom_bank_mask = (value >> 2) & 0x0F;
+                    self.multiplex = value & 0x40 != 0;
+                }
+            }
+        }
+        false
+    }
+
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        match ram_bank_offset(ram, self.ram_bank(), address) {
+            Some(offset) if self.ram_enabled => ram[offset],
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        match ram_bank_offset(ram, self.ram_bank(), address) {
+            Some(offset) if self.ram_enabled => {
+                ram[offset] = value;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn rom_bank(&self, address: u16) -> usize {
+        if !self.mapped {
+            return MENU_BANK | (address as usize >> 14);
+        }
+        let mid = if !self.multiplex {
+            self.rom_bank_mid
+        } else if self.mode {
+            0
+        } else {
+            self.ram_bank_low
+        };
+        let outer = ((mid as usize) << 5) | ((self.rom_bank_high as usize) << 7);
+        match address {
+            0x0000..=0x3FFF => outer | (self.rom_bank_low & self.frozen_rom_bits()) as usize,
+            _ => {
+                // Like MBC1, bank 0 of the game cannot be selected in the upper window.
+                let low = if self.rom_bank_low & !self.frozen_rom_bits() == 0 {
+                    self.rom_bank_low | 1
+                } else {
+                    self.rom_bank_low
+                };
+                outer | low as usize
+            }
+        }
+    }
+
+    fn requests_save(&self) -> bool {
+        !self.ram_enabled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_menu_maps_a_game_and_locks_its_outer_bits() {
+        let rom: Vec<u8> = (0..64).flat_map(|bank| vec![bank as u8; 0x4000]).collect();
+        let mut mbc = Mmm01::new();
+        assert_eq!(mbc.read_rom(&rom, 0x0000), 62);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 63);
+
+        // A 128 KiB game starting at bank 0x28: mask off bits 3-4 of BANK1.
+        mbc.write_register(0x6000, 0b1100 << 2);
+        mbc.write_register(0x2000, 0x28);
+        mbc.write_register(0x4000, 0x00);
+        mbc.write_register(0x0000, 0x40);
+        assert_eq!(mbc.read_rom(&rom, 0x0000), 0x28);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x29);
+
+        mbc.write_register(0x2000, 0x07);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x2F);
+        mbc.write_register(0x0000, 0x00);
+        mbc.write_register(0x6000, 0x00);
+        assert_eq!(mbc.read_rom(&rom, 0x0000), 0x28);
+    }
+}
@@ -0,0 +1,354 @@
+mod camera;
+mod header;
+mod huc1;
+mod huc3;
+mod mbc1;
+mod mbc2;
+mod mbc3;
+mod mbc5;
+mod mbc6;
+mod mbc7;
+mod mmm01;
+mod patch;
+mod rtc;
+mod tama5;
+
+pub use camera::IMAGE_SIZE as CAMERA_IMAGE_SIZE;
+#[cfg(test)]
+pub(crate) use header::header_checksum_of;
+pub use header::CartridgeHeader;
+pub use patch::{apply as apply_patch, PATCH_EXTENSIONS};
+pub use rtc::RtcClock;
+
+use crate::emulator::error::CartridgeError;
+use anyhow::Result;
+use camera::PocketCamera;
+use header::{Mapper, ROM_BANK_SIZE};
+use huc1::HuC1;
+use huc3::HuC3;
+use mbc1::Mbc1;
+use mbc2::Mbc2;
+use mbc3::Mbc3;
+use mbc5::Mbc5;
+use mbc6::Mbc6;
+use mbc7::Mbc7;
+use mmm01::Mmm01;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use tama5::Tama5;
+
+const RAM_BANK_SIZE: usize = 0x2000;
+
+/// A memory bank controller. It decides which ROM and RAM banks the CPU sees, and
+/// writes to ROM space go to its registers instead of the ROM.
+pub trait Mbc {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8;
+    /// Handles a write to ROM space, returning whether it changed battery-backed
+    /// state, as programming flash through the ROM window does.
+    fn write_register(&mut self, address: u16, value: u8) -> bool;
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8;
+    /// Handles a write to 0xA000-0xBFFF, returning whether it changed battery-backed
+    /// state. Writes to disabled RAM or to register ports in that window do not.
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool;
+    /// The ROM bank mapped at `address`, for use in diagnostics.
+    fn rom_bank(&self, address: u16) -> usize;
+    /// Advances anything on the cartridge that runs off the clock by one M-cycle.
+    fn tick(&mut self, _double_speed: bool) {}
+    /// Whether the cartridge's rumble motor is running.
+    fn rumble(&self) -> bool {
+        false
+    }
+    /// Feeds a tilt sensor, in g along the x (right) and y (down) axes.
+    fn set_tilt(&mut self, _x: f32, _y: f32) {}
+    /// Feeds a camera sensor with a 128x112 picture, one grey level per pixel.
+    fn set_image(&mut self, _image: &[u8]) {}
+    /// Battery-backed state kept outside RAM, such as a clock, in the layout other
+    /// emulators append to RAM in save files.
+    fn battery_data(&self) -> Vec<u8> {
+        Vec::new()
+    }
+    fn load_battery_data(&mut self, _data: &[u8]) {}
+    /// Whether the game has shut off battery-backed memory, as it does once it is
+    /// done saving.
+    fn requests_save(&self) -> bool {
+        false
+    }
+}
+
+/// A cartridge without a controller: 32 KiB of ROM and, optionally, 8 KiB of RAM.
+struct RomOnly;
+
+impl Mbc for RomOnly {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        read_rom_bank(rom, self.rom_bank(address), address)
+    }
+
+    fn write_register(&mut self, _address: u16, _value: u8) -> bool {
+        false
+    }
+
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        ram_bank_offset(ram, 0, address).map_or(0xFF, |offset| ram[offset])
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        let Some(offset) = ram_bank_offset(ram, 0, address) else {
+            return false;
+        };
+        ram[offset] = value;
+        true
+    }
+
+    fn rom_bank(&self, address: u16) -> usize {
+        (address as usize) / ROM_BANK_SIZE
+    }
+}
+
+// Reads `address` from a 16 KiB ROM bank. Bank numbers wrap around the ROM size, as
+// the bank bits beyond the size of the ROM are not connected to anything.
+fn read_rom_bank(rom: &[u8], bank: usize, address: u16) -> u8 {
+    if rom.is_empty() {
+        return 0xFF;
+    }
+    rom[(bank * ROM_BANK_SIZE + (address as usize % ROM_BANK_SIZE)) % rom.len()]
+}
+
+// The offset of `address` (0xA000-0xBFFF) within an 8 KiB RAM bank, or `None` if the
+// cartridge has no RAM.
+fn ram_bank_offset(ram: &[u8], bank: usize, address: u16) -> Option<usize> {
+    if ram.is_empty() {
+        return None;
+    }
+    Some((bank * RAM_BANK_SIZE + (address as usize - 0xA000)) % ram.len())
+}
+
+pub struct Cartridge {
+    pub rom: Vec<u8>,
+    ram: Vec<u8>,
+    header: Option<CartridgeHeader>,
+    mbc: Box<dyn Mbc>,
+    // Where battery-backed state is kept, if the cartridge has a battery.
+    save_path: Option<PathBuf>,
+    // Whether battery-backed state has changed since the last save, and whether the
+    // game has since shut it off, which it does once it is done saving.
+    dirty: bool,
+    save_requested: bool,
+}
+
+impl Cartridge {
+    pub fn new() -> Cartridge {
+        Cartridge {
+            rom: Vec::new(),
+            ram: Vec::new(),
+            header: None,
+            mbc: Box::new(RomOnly),
+            save_path: None,
+            dirty: false,
+            save_requested: false,
+        }
+    }
+
+    /// Sets up a cartridge from a ROM image held in memory, checking its header. The
+    /// image may be larger than the ROM size in the header, as some multicarts and
+    /// test ROMs are, but not smaller. `rtc_clock` drives the clock of cartridges
+    /// that have one.
+    pub fn from_bytes(rom: Vec<u8>, rtc_clock: RtcClock) -> Result<Cartridge> {
+        let header = CartridgeHeader::parse(&rom)?;
+        if rom.len() < header.rom_size {
+            return Err(CartridgeError::RomSizeMismatch {
+                declared: header.rom_size,
+                actual: rom.len(),
+            }
+            .into());
+        }
+
+        let cartridge_type = header.cartridge_type;
+        // Some controllers bring their own memory, which the header does not count.
+        let (mbc, ram_size): (Box<dyn Mbc>, usize) = match cartridge_type.mapper {
+            Mapper::None => (Box::new(RomOnly), header.ram_size),
+            Mapper::Mbc1 => (Box::new(Mbc1::new(&rom)), header.ram_size),
+            Mapper::Mbc2 => (Box::new(Mbc2::new()), mbc2::RAM_SIZE),
+            Mapper::Mbc3 => (
+                Box::new(Mbc3::new(&rom, cartridge_type.timer, rtc_clock)),
+                header.ram_size,
+            ),
+            Mapper::Mbc5 => (Box::new(Mbc5::new(cartridge_type.rumble)), header.ram_size),
+            Mapper::Mbc6 => (Box::new(Mbc6::new()), header.ram_size),
+            Mapper::Mbc7 => (Box::new(Mbc7::new()), mbc7::EEPROM_SIZE),
+            Mapper::Mmm01 => (Box::new(Mmm01::new()), header.ram_size),
+            Mapper::HuC1 => (Box::new(HuC1::new()), header.ram_size),
+            Mapper::HuC3 => (Box::new(HuC3::new(rtc_clock)), header.ram_size),
+            Mapper::Tama5 => (Box::new(Tama5::new(rtc_clock)), tama5::RAM_SIZE),
+            Mapper::PocketCamera => (Box::new(PocketCamera::new()), header.ram_size),
+            Mapper::Unsupported => {
+                return Err(CartridgeError::UnsupportedMapper(cartridge_type.code).into())
+            }
+        };
+        Ok(Cartridge {
+            rom,
+            ram: vec![0; ram_size],
+            header: Some(header),
+            mbc,
+            save_path: None,
+            dirty: false,
+            save_requested: false,
+        })
+    }
+
+    /// Keeps battery-backed state in `path`, picking up what is already saved there.
+    /// Does nothing for cartridges without a battery.
+    pub fn set_save_path(&mut self, path: PathBuf) -> Result<()> {
+        let battery = self
+            .header
+            .as_ref()
+            .is_some_and(|header| header.cartridge_type.battery);
+        if battery {
+            self.load_save(&path)?;
+            self.save_path = Some(path);
+        }
+        Ok(())
+    }
+
+    // Save files hold RAM followed by whatever else the controller keeps powered,
+    // such as a clock, as BGB, SameBoy and mGBA lay them out.
+    fn load_save(&mut self, path: &Path) -> Result<()> {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(error) => return Err(error.into()),
+        };
+        let ram_length = data.len().min(self.ram.len());
+        self.ram[..ram_length].copy_from_slice(&data[..ram_length]);
+        self.mbc.load_battery_data(&data[ram_length..]);
+        Ok(())
+    }
+
+    /// Writes battery-backed state to the save file. The file is replaced in one go,
+    /// so that it is never left half written.
+    pub fn save(&mut self) -> io::Result<()> {
+        let Some(path) = &self.save_path else {
+            return Ok(());
+        };
+        let mut data = self.ram.clone();
+        data.extend(self.mbc.battery_data());
+        let temporary = path.with_extension("sav.tmp");
+        fs::write(&temporary, data)?;
+        fs::rename(&temporary, path)?;
+        self.dirty = false;
+        self.save_requested = false;
+        Ok(())
+    }
+
+    pub fn save_path(&self) -> Option<&Path> {
+        self.save_path.as_deref()
+    }
+
+    /// Whether RAM has changed since the last save.
+    pub fn dirty(&self) -> bool {
+        self.dirty && self.save_path.is_some()
+    }
+
+    /// Whether the game has changed RAM and then disabled it, a sign that it has
+    /// finished saving.
+    pub fn save_requested(&self) -> bool {
+        self.save_requested && self.save_path.is_some()
+    }
+
+    pub fn header(&self) -> Option<&CartridgeHeader> {
+        self.header.as_ref()
+    }
+
+    /// Reads from the cartridge's ROM (0x0000-0x7FFF) or external RAM (0xA000-0xBFFF).
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x7FFF => self.mbc.read_rom(&self.rom, address),
+            0xA000..=0xBFFF => self.mbc.read_ram(&self.ram, address),
+            _ => unreachable!("{:#06X} is not a cartridge address", address),
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        let changed = match address {
+            0x0000..=0x7FFF => self.mbc.write_register(address, value),
+            0xA000..=0xBFFF => self.mbc.write_ram(&mut self.ram, address, value),
+            _ => unreachable!("{:#06X} is not a cartridge address", address),
+        };
+        self.dirty |= changed;
+        if self.dirty && self.mbc.requests_save() {
+            self.save_requested = true;
+        }
+    }
+
+    pub fn rom_bank(&self, address: u16) -> usize {
+        self.mbc.rom_bank(address)
+    }
+
+    pub fn tick(&mut self, double_speed: bool) {
+        self.mbc.tick(double_speed);
+    }
+
+    pub fn rumble(&self) -> bool {
+        self.mbc.rumble()
+    }
+
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.mbc.set_tilt(x, y);
+    }
+
+    pub fn set_camera_image(&mut self, image: &[u8]) {
+        self.mbc.set_image(image);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::test_support::{blank_rom, scratch_directory};
+
+    #[test]
+    fn test_battery_ram_and_clock_round_trip_through_the_save_file() {
+        let directory = scratch_directory("battery_ram_and_clock_round_trip");
+        // MBC3+TIMER+RAM+BATTERY with one bank of RAM.
+        let rom = blank_rom(0x10, 0x02);
+        let save_path = directory.join("game.sav");
+
+        let mut cartridge = Cartridge::from_bytes(rom.clone(), RtcClock::Emulated).unwrap();
+        cartridge.set_save_path(save_path.clone()).unwrap();
+        cartridge.write(0x0000, 0x0A);
+        cartridge.write(0xA000, 0x42);
+        assert!(cartridge.dirty() && !cartridge.save_requested());
+        cartridge.write(0x0000, 0x00);
+        assert!(cartridge.save_requested());
+        cartridge.save().unwrap();
+        assert!(!cartridge.dirty());
+
+        assert_eq!(fs::read(&save_path).unwrap().len(), 0x2000 + 48);
+        let mut restored = Cartridge::from_bytes(rom, RtcClock::Emulated).unwrap();
+        restored.set_save_path(save_path).unwrap();
+        restored.write(0x0000, 0x0A);
+        assert_eq!(restored.read(0xA000), 0x42);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_register_ports_and_disabled_ram_are_not_saved() {
+        let directory = scratch_directory("register_ports_are_not_saved");
+        // MBC7+SENSOR+RUMBLE+RAM+BATTERY.
+        let mut cartridge =
+            Cartridge::from_bytes(blank_rom(0x22, 0x00), RtcClock::Emulated).unwrap();
+        let save_path = directory.join("game.sav");
+        cartridge.set_save_path(save_path).unwrap();
+
+        cartridge.write(0xA080, 0xFF);
+        assert!(!cartridge.dirty());
+        // Latching the accelerometer goes through the RAM window but saves nothing.
+        cartridge.write(0x0000, 0x0A);
+        cartridge.write(0x4000, 0x40);
+        cartridge.write(0xA000, 0x55);
+        cartridge.write(0xA010, 0xAA);
+        cartridge.write(0x0000, 0x00);
+        assert!(!cartridge.dirty() && !cartridge.save_requested());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,360 @@
+use crate::emulator::error::PatchError;
+
+/// The extensions patch files are distributed with, in the order they are looked for.
+pub const PATCH_EXTENSIONS: [&str; 3] = ["ips", "ups", "bps"];
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_END: &[u8] = b"EOF";
+const UPS_MAGIC: &[u8] = b"UPS1";
+const BPS_MAGIC: &[u8] = b"BPS1";
+
+// UPS and BPS end in the CRC32s of the source, the target and the patch itself.
+const FOOTER_SIZE: usize = 12;
+
+/// Applies an IPS, UPS or BPS patch to a ROM image, telling the format from the
+/// patch's signature. UPS and BPS patches are checked against the CRC32s they carry,
+/// so a patch made for a different ROM, or a damaged one, is refused.
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if patch.starts_with(IPS_MAGIC) {
+        apply_ips(rom, &patch[IPS_MAGIC.len()..])
+    } else if patch.starts_with(UPS_MAGIC) {
+        let (body, footer) = split_footer(rom, patch)?;
+        let target = apply_ups(rom, &body[UPS_MAGIC.len()..])?;
+        footer.check_target(&target)?;
+        Ok(target)
+    } else if patch.starts_with(BPS_MAGIC) {
+        let (body, footer) = split_footer(rom, patch)?;
+        let target = apply_bps(rom, &body[BPS_MAGIC.len()..])?;
+        footer.check_target(&target)?;
+        Ok(target)
+    } else {
+        Err(PatchError::UnknownFormat)
+    }
+}
+
+struct Footer {
+    target_crc: u32,
+}
+
+impl Footer {
+    fn check_target(&self, target: &[u8]) -> Result<(), PatchError> {
+        check_crc(self.target_crc, target, |expected, actual| {
+            PatchError::TargetChecksum { expected, actual }
+        })
+    }
+}
+
+// Checks the patch and source CRC32s in the footer, returning the rest of the patch
+// and the target CRC32 to check once the patch has been applied.
+fn split_footer<'a>(rom: &[u8], patch: &'a [u8]) -> Result<(&'a [u8], Footer), PatchError> {
+    if patch.len() < 4 + FOOTER_SIZE {
+        return Err(PatchError::Malformed);
+    }
+    let (body, footer) = patch.split_at(patch.len() - FOOTER_SIZE);
+    let crc =
+        |index: usize| u32::from_le_bytes(footer[index * 4..index * 4 + 4].try_into().unwrap());
+    check_crc(crc(2), &patch[..patch.len() - 4], |expected, actual| {
+        PatchError::PatchChecksum { expected, actual }
+    })?;
+    check_crc(crc(0), rom, |expected, actual| PatchError::SourceChecksum {
+        expected,
+        actual,
+    })?;
+    Ok((body, Footer { target_crc: crc(1) }))
+}
+
+fn check_crc(
+    expected: u32,
+    data: &[u8],
+    error: impl FnOnce(u32, u32) -> PatchError,
+) -> Result<(), PatchError> {
+    let actual = crc32fast::hash(data);
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(error(expected, actual))
+    }
+}
+
+// Reads through a patch, failing as malformed if it ends early.
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data, position: 0 }
+    }
+
+    fn done(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], PatchError> {
+        let bytes = slice(self.data, self.position, length)?;
+        self.position += length;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, PatchError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn big_endian(&mut self, length: usize) -> Result<usize, PatchError> {
+        Ok(self
+            .bytes(length)?
+            .iter()
+            .fold(0, |value, &byte| (value << 8) | byte as usize))
+    }
+
+    // UPS and BPS numbers: seven bits a byte, least significant first, with the top
+    // bit marking the last byte. Each continuation adds one more so that every
+    // number has a single encoding.
+    fn number(&mut self) -> Result<usize, PatchError> {
+        let mut value: usize = 0;
+        let mut shift: usize = 1;
+        loop {
+            let byte = self.byte()?;
+            value = (byte as usize & 0x7F)
+                .checked_mul(shift)
+                .and_then(|bits| value.checked_add(bits))
+                .ok_or(PatchError::Malformed)?;
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift = shift.checked_mul(0x80).ok_or(PatchError::Malformed)?;
+            value = value.checked_add(shift).ok_or(PatchError::Malformed)?;
+        }
+    }
+}
+
+// `length` bytes of `data` from `start`, or an error if that runs past the end.
+fn slice(data: &[u8], start: usize, length: usize) -> Result<&[u8], PatchError> {
+    start
+        .checked_add(length)
+        .and_then(|end| data.get(start..end))
+        .ok_or(PatchError::Malformed)
+}
+
+// IPS records overwrite or fill runs of bytes at 24-bit offsets, growing the ROM if
+// they go past its end. An optional length after the end marker truncates it.
+fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let mut target = rom.to_vec();
+    let mut reader = Reader::new(patch);
+    loop {
+        let offset = reader.bytes(3)?;
+        if offset == IPS_END {
+            break;
+        }
+        let offset = offset
+            .iter()
+            .fold(0, |value, &byte| (value << 8) | byte as usize);
+        let length = reader.big_endian(2)?;
+        let (length, data) = if length == 0 {
+            // Run-length encoded: a count, then the byte to repeat.
+            let count = reader.big_endian(2)?;
+            (count, None)
+        } else {
+            (length, Some(reader.bytes(length)?))
+        };
+        if target.len() < offset + length {
+            target.resize(offset + length, 0);
+        }
+        match data {
+            Some(data) => target[offset..offset + length].copy_from_slice(data),
+            None => target[offset..offset + length].fill(reader.byte()?),
+        }
+    }
+    if !reader.done() {
+        target.truncate(reader.big_endian(3)?);
+    }
+    Ok(target)
+}
+
+// UPS gives the distance to each changed run and the run XORed with the source,
+// ending in a zero that also counts as an unchanged byte.
+fn apply_ups(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let mut reader = Reader::new(patch);
+    let _source_size = reader.number()?;
+    let target_size = reader.number()?;
+    let mut target = rom.to_vec();
+    target.resize(target_size, 0);
+    let mut position: usize = 0;
+    while !reader.done() {
+        position = position
+            .checked_add(reader.number()?)
+            .ok_or(PatchError::Malformed)?;
+        loop {
+            let byte = reader.byte()?;
+            if byte == 0 {
+                break;
+            }
+            if let Some(target) = target.get_mut(position) {
+                *target = rom.get(position).copied().unwrap_or(0) ^ byte;
+            }
+            position += 1;
+        }
+        position += 1;
+    }
+    Ok(target)
+}
+
+// BPS builds the target from four kinds of copy: from the same place in the source,
+// from the patch, and from anywhere in the source or the target written so far.
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let mut reader = Reader::new(patch);
+    let _source_size = reader.number()?;
+    let target_size = reader.number()?;
+    let metadata_size = reader.number()?;
+    reader.bytes(metadata_size)?;
+
+    let mut target = Vec::new();
+    let mut source_offset: usize = 0;
+    let mut target_offset: usize = 0;
+    // Relative offsets are stored as a magnitude with the sign in the lowest bit.
+    let relative = |offset: usize, delta: usize| {
+        let magnitude = delta >> 1;
+        if delta & 1 != 0 {
+            offset.checked_sub(magnitude)
+        } else {
+            offset.checked_add(magnitude)
+        }
+        .ok_or(PatchError::Malformed)
+    };
+    while !reader.done() {
+        let action = reader.number()?;
+        let length = (action >> 2) + 1;
+        match action & 0b11 {
+            0 => target.extend_from_slice(slice(rom, target.len(), length)?),
+            1 => target.extend_from_slice(reader.bytes(length)?),
+            2 => {
+                source_offset = relative(source_offset, reader.number()?)?;
+                target.extend_from_slice(slice(rom, source_offset, length)?);
+                source_offset += length;
+            }
+            _ => {
+                target_offset = relative(target_offset, reader.number()?)?;
+                // The copy may overlap what it writes, repeating a pattern.
+                for _ in 0..length {
+                    let byte = *target.get(target_offset).ok_or(PatchError::Malformed)?;
+                    target.push(byte);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+    if target.len() != target_size {
+        return Err(PatchError::Malformed);
+    }
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(mut value: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        loop {
+            let low = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                bytes.push(0x80 | low);
+                return bytes;
+            }
+            bytes.push(low);
+            value -= 1;
+        }
+    }
+
+    fn with_footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+        patch.extend_from_slice(&crc32fast::hash(source).to_le_bytes());
+        patch.extend_from_slice(&crc32fast::hash(target).to_le_bytes());
+        let crc = crc32fast::hash(&patch);
+        patch.extend_from_slice(&crc.to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn test_ips_records_run_lengths_and_growth() {
+        let rom = [0u8; 8];
+        let patch = [
+            b"PATCH".as_slice(),
+            &[0x00, 0x00, 0x02, 0x00, 0x02, 0xAA, 0xBB],
+            &[0x00, 0x00, 0x07, 0x00, 0x00, 0x00, 0x03, 0xCC],
+            b"EOF",
+        ]
+        .concat();
+        assert_eq!(
+            apply(&rom, &patch).unwrap(),
+            [0, 0, 0xAA, 0xBB, 0, 0, 0, 0xCC, 0xCC, 0xCC]
+        );
+
+        let truncated = [patch, vec![0x00, 0x00, 0x04]].concat();
+        assert_eq!(apply(&rom, &truncated).unwrap(), [0, 0, 0xAA, 0xBB]);
+    }
+
+    #[test]
+    fn test_ups_xors_runs_and_checks_crcs() {
+        let rom = [1, 2, 3, 4, 5, 6];
+        let target = [1, 2, 0xFF, 4, 5, 6, 7];
+        let body = [
+            b"UPS1".as_slice(),
+            &number(rom.len()),
+            &number(target.len()),
+            &number(2),
+            &[3 ^ 0xFF, 0x00],
+            &number(2),
+            &[7, 0x00],
+        ]
+        .concat();
+        let patch = with_footer(body, &rom, &target);
+        assert_eq!(apply(&rom, &patch).unwrap(), target);
+
+        assert_eq!(
+            apply(&[9, 9, 9], &patch),
+            Err(PatchError::SourceChecksum {
+                expected: crc32fast::hash(&rom),
+                actual: crc32fast::hash(&[9, 9, 9]),
+            })
+        );
+        let mut damaged = patch.clone();
+        damaged[8] ^= 1;
+        assert!(matches!(
+            apply(&rom, &damaged),
+            Err(PatchError::PatchChecksum { .. })
+        ));
+    }
+
+    #[test]
+    fn test_bps_copies_from_source_patch_and_target() {
+        let rom = [10, 20, 30, 40];
+        let target = [10, 20, 0xEE, 30, 40, 0xEE, 30, 40, 0xEE, 30, 40];
+        let body = [
+            b"BPS1".as_slice(),
+            &number(rom.len()),
+            &number(target.len()),
+            &number(0),
+            // Two bytes from the source, one from the patch.
+            &number(1 << 2),
+            &number(1),
+            &[0xEE],
+            // Two from source offset 2, then six repeating the target from offset 2.
+            &number((1 << 2) | 2),
+            &number(2 << 1),
+            &number((5 << 2) | 3),
+            &number(2 << 1),
+        ]
+        .concat();
+        let patch = with_footer(body, &rom, &target);
+        assert_eq!(apply(&rom, &patch).unwrap(), target);
+
+        let wrong_target = with_footer(patch[..patch.len() - 12].to_vec(), &rom, &[0]);
+        assert!(matches!(
+            apply(&rom, &wrong_target),
+            Err(PatchError::TargetChecksum { .. })
+        ));
+        assert_eq!(apply(&rom, b"NOT A PATCH"), Err(PatchError::UnknownFormat));
+    }
+}
@@ -0,0 +1,344 @@
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+// The RTC counts seconds off its own 32.768 kHz crystal, which is 128 times slower
+// than the CPU's base clock. Time is kept in CPU cycles at single speed.
+const CYCLES_PER_SECOND: u64 = 4_194_304;
+
+const SECONDS: usize = 0;
+const MINUTES: usize = 1;
+const HOURS: usize = 2;
+const DAYS_LOW: usize = 3;
+const DAYS_HIGH: usize = 4;
+
+// The bits each register has; the others always read back as 0.
+const REGISTER_MASKS: [u8; 5] = [0x3F, 0x3F, 0x1F, 0xFF, 0xC1];
+
+// Save files end in the live and latched registers as 32-bit words and a 64-bit Unix
+// time, the layout BGB introduced. VBA wrote a 32-bit time instead.
+const SAVE_LENGTH: usize = 48;
+const OLD_SAVE_LENGTH: usize = 44;
+
+// The day counter overflows after 512 days, by which time every counter is back in
+// range and a further 512 days leave them all as they were.
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+const DAY_COUNTER_PERIOD: u64 = 512 * SECONDS_PER_DAY;
+
+const DAY_BIT_8: u8 = 0b0000_0001;
+const HALT: u8 = 0b0100_0000;
+const DAY_CARRY: u8 = 0b1000_0000;
+
+/// What drives the RTC forward.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RtcClock {
+    /// Emulated cycles, so runs are reproducible and the clock stands still while
+    /// the emulator is paused.
+    Emulated,
+    /// The host's clock, as a real cartridge keeps running in the background.
+    Host,
+}
+
+/// Counts the seconds of a cartridge clock, off either emulated cycles or the host.
+pub struct Crystal {
+    clock: RtcClock,
+    // Cycles into the current second.
+    subsecond: u64,
+    synced_at: Instant,
+}
+
+impl Crystal {
+    pub fn new(clock: RtcClock) -> Self {
+        Crystal {
+            clock,
+            subsecond: 0,
+            synced_at: Instant::now(),
+        }
+    }
+
+    /// Advances an emulated clock by one M-cycle. Returns the seconds that completed.
+    pub fn tick(&mut self, double_speed: bool) -> u64 {
+        match self.clock {
+            RtcClock::Emulated => self.advance(if double_speed { 2 } else { 4 }),
+            RtcClock::Host => 0,
+        }
+    }
+
+    /// Brings a host-driven clock up to date. Returns the seconds that completed.
+    pub fn sync(&mut self) -> u64 {
+        match self.clock {
+            RtcClock::Emulated => 0,
+            RtcClock::Host => {
+                let now = Instant::now();
+                let elapsed = now.duration_since(self.synced_at);
+                self.synced_at = now;
+                self.advance(
+                    (elapsed.as_nanos() * CYCLES_PER_SECOND as u128 / 1_000_000_000) as u64,
+                )
+            }
+        }
+    }
+
+    /// How long ago a save made at `timestamp` was, for a host-driven clock to
+    /// catch up on. An emulated clock carries on from where it was saved.
+    pub fn seconds_since(&self, timestamp: u64) -> u64 {
+        match self.clock {
+            RtcClock::Emulated => 0,
+            RtcClock::Host => unix_time().saturating_sub(timestamp),
+        }
+    }
+
+    pub fn restart_second(&mut self) {
+        self.subsecond = 0;
+    }
+
+    fn advance(&mut self, cycles: u64) -> u64 {
+        self.subsecond += cycles;
+        let seconds = self.subsecond / CYCLES_PER_SECOND;
+        self.subsecond %= CYCLES_PER_SECOND;
+        seconds
+    }
+}
+
+/// The MBC3 real-time clock: seconds, minutes, hours and a 9-bit day counter, read
+/// through a latched copy so the value does not change halfway through a read.
+pub struct Rtc {
+    crystal: Crystal,
+    live: [u8; 5],
+    latched: [u8; 5],
+}
+
+impl Rtc {
+    pub fn new(clock: RtcClock) -> Self {
+        Rtc {
+            crystal: Crystal::new(clock),
+            live: [0; 5],
+            latched: [0; 5],
+        }
+    }
+
+    pub fn tick(&mut self, double_speed: bool) {
+        // The halt bit stops the oscillator, so the second in progress is kept too.
+        if !self.halted() {
+            let seconds = self.crystal.tick(double_speed);
+            self.run(seconds);
+        }
+    }
+
+    pub fn latch(&mut self) {
+        self.sync();
+        self.latched = self.live;
+    }
+
+    /// Reads a latched register, selected by 0x08-0x0C.
+    pub fn read(&self, select: u8) -> u8 {
+        self.latched[Self::index(select)]
+    }
+
+    /// Writes a live register. Values the counters never reach can still be written,
+    /// and count up to the top of the register before wrapping to 0 without a carry.
+    pub fn write(&mut self, select: u8, value: u8) {
+        self.sync();
+        let index = Self::index(select);
+        let value = value & REGISTER_MASKS[index];
+        self.live[index] = value;
+        self.latched[index] = value;
+        // Writing the seconds restarts the second in progress.
+        if index == SECONDS {
+            self.crystal.restart_second();
+        }
+    }
+
+    pub fn save(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(SAVE_LENGTH);
+        for register in self.live.iter().chain(&self.latched) {
+            data.extend_from_slice(&(*register as u32).to_le_bytes());
+        }
+        data.extend_from_slice(&unix_time().to_le_bytes());
+        data
+    }
+
+    /// Restores the registers from a save file's trailer, ignoring it if it is not
+    /// one of the known layouts.
+    pub fn load(&mut self, data: &[u8]) {
+        let timestamp = match data.len() {
+            SAVE_LENGTH => u64::from_le_bytes(data[40..48].try_into().unwrap()),
+            OLD_SAVE_LENGTH => u32::from_le_bytes(data[40..44].try_into().unwrap()) as u64,
+            _ => return,
+        };
+        let mut words = data.chunks_exact(4).map(|word| word[0]);
+        for (index, register) in self.live.iter_mut().chain(&mut self.latched).enumerate() {
+            *register = words.next().unwrap_or(0) & REGISTER_MASKS[index % 5];
+        }
+        if !self.halted() {
+            let seconds = self.crystal.seconds_since(timestamp);
+            self.run(seconds);
+        }
+    }
+
+    fn halted(&self) -> bool {
+        self.live[DAYS_HIGH] & HALT != 0
+    }
+
+    fn index(select: u8) -> usize {
+        (select - 0x08) as usize
+    }
+
+    fn sync(&mut self) {
+        let seconds = self.crystal.sync();
+        if !self.halted() {
+            self.run(seconds);
+        }
+    }
+
+    // Catching up on a long gap, such as a save file from years ago, works out each
+    // counter at once rather than counting every second in between.
+    fn run(&mut self, seconds: u64) {
+        let seconds = if seconds > DAY_COUNTER_PERIOD {
+            DAY_COUNTER_PERIOD + seconds % DAY_COUNTER_PERIOD
+        } else {
+            seconds
+        };
+        let minutes = Self::count(
+            &mut self.live[SECONDS],
+            seconds,
+            60,
+            REGISTER_MASKS[SECONDS],
+        );
+        let hours = Self::count(
+            &mut self.live[MINUTES],
+            minutes,
+            60,
+            REGISTER_MASKS[MINUTES],
+        );
+        let days = Self::count(&mut self.live[HOURS], hours, 24, REGISTER_MASKS[HOURS]);
+
+        let day_high = (self.live[DAYS_HIGH] & DAY_BIT_8) as u64;
+        let day = ((day_high << 8) | self.live[DAYS_LOW] as u64) + days;
+        self.live[DAYS_LOW] = day as u8;
+        self.live[DAYS_HIGH] = (self.live[DAYS_HIGH] & !DAY_BIT_8) | ((day >> 8) & 0b1) as u8;
+        if day > 0x1FF {
+            self.live[DAYS_HIGH] |= DAY_CARRY;
+        }
+    }
+
+    // Counts a register up `count` times, returning how often it rolled over into the
+    // next. A value at or above `limit` counts on up to `mask` and wraps to 0 without
+    // rolling over.
+    fn count(register: &mut u8, count: u64, limit: u8, mask: u8) -> u64 {
+        let mut count = count;
+        if *register >= limit {
+            let to_wrap = (mask - *register) as u64 + 1;
+            if count < to_wrap {
+                *register += count as u8;
+                return 0;
+            }
+            count -= to_wrap;
+            *register = 0;
+        }
+        let total = *register as u64 + count;
+        *register = (total % limit as u64) as u8;
+        total / limit as u64
+    }
+}
+
+/// Seconds since the Unix epoch, as save files record them.
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rtc_at(registers: [u8; 5]) -> Rtc {
+        let mut rtc = Rtc::new(RtcClock::Emulated);
+        for (index, value) in registers.into_iter().enumerate() {
+            rtc.write(0x08 + index as u8, value);
+        }
+        rtc
+    }
+
+    fn run_seconds(rtc: &mut Rtc, seconds: u64) {
+        for _ in 0..seconds * CYCLES_PER_SECOND / 4 {
+            rtc.tick(false);
+        }
+    }
+
+    #[test]
+    fn test_counters_roll_over_into_days_and_carry() {
+        let mut rtc = rtc_at([59, 59, 23, 0xFF, 0x01]);
+        run_seconds(&mut rtc, 1);
+        assert_eq!(rtc.read(0x08), 59);
+        rtc.latch();
+        assert_eq!(rtc.latched, [0, 0, 0, 0, DAY_CARRY]);
+    }
+
+    #[test]
+    fn test_out_of_range_values_wrap_without_carry() {
+        let mut rtc = rtc_at([63, 0xFF, 31, 0, 0]);
+        assert_eq!(rtc.read(0x09), 0x3F);
+        run_seconds(&mut rtc, 1);
+        rtc.latch();
+        assert_eq!(rtc.latched, [0, 0x3F, 31, 0, 0]);
+    }
+
+    #[test]
+    fn test_writing_seconds_restarts_the_second_and_halt_stops_the_clock() {
+        let mut rtc = rtc_at([0, 0, 0, 0, 0]);
+        run_seconds(&mut rtc, 1);
+        rtc.tick(false);
+        rtc.write(0x08, 10);
+        for _ in 0..CYCLES_PER_SECOND / 4 - 1 {
+            rtc.tick(false);
+        }
+        rtc.latch();
+        assert_eq!(rtc.read(0x08), 10);
+
+        rtc.write(0x0C, HALT);
+        run_seconds(&mut rtc, 2);
+        rtc.latch();
+        assert_eq!(rtc.read(0x08), 10);
+        assert_eq!(rtc.read(0x0C), HALT);
+    }
+
+    #[test]
+    fn test_long_gaps_are_caught_up_at_once() {
+        let mut rtc = rtc_at([58, 59, 23, 0xFF, 0x00]);
+        rtc.run(2 + 3 * SECONDS_PER_DAY + 5 * 3600 + 7 * 60 + 9);
+        assert_eq!(rtc.live, [9, 7, 5, 0x03, DAY_BIT_8]);
+
+        let mut rtc = rtc_at([63, 0, 31, 0, 0]);
+        rtc.run(1);
+        assert_eq!(rtc.live, [0, 0, 31, 0, 0]);
+        rtc.run(60 * 60);
+        assert_eq!(rtc.live, [0, 0, 0, 0, 0]);
+
+        // Decades away, as from a save with a bogus timestamp, only the remainder of
+        // the last 512 days shows, besides the carry.
+        let mut rtc = rtc_at([0, 0, 0, 0, 0]);
+        rtc.run(40 * DAY_COUNTER_PERIOD + 10 * SECONDS_PER_DAY + 1);
+        assert_eq!(rtc.live, [1, 0, 0, 10, DAY_CARRY]);
+    }
+
+    #[test]
+    fn test_save_trailer_round_trips_and_host_clock_catches_up() {
+        let rtc = rtc_at([30, 59, 23, 0x12, 0x01]);
+        let data = rtc.save();
+        assert_eq!(data.len(), SAVE_LENGTH);
+        assert_eq!(data[..8], [30, 0, 0, 0, 59, 0, 0, 0]);
+
+        let mut restored = Rtc::new(RtcClock::Emulated);
+        restored.load(&data);
+        assert_eq!(restored.live, rtc.live);
+
+        let mut old = data[..OLD_SAVE_LENGTH].to_vec();
+        old[40..44].copy_from_slice(&((unix_time() - 40) as u32).to_le_bytes());
+        let mut restored = Rtc::new(RtcClock::Host);
+        restored.load(&old);
+        restored.latch();
+        // The wall clock may tick over while the test runs.
+        assert!((10..=11).contains(&restored.latched[0]));
+        assert_eq!(restored.latched[1..], [0, 0, 0x13, 0x01]);
+    }
+}
@@ -0,0 +1,416 @@
+use super::rtc::{unix_time, Crystal, RtcClock};
+use super::{read_rom_bank, Mbc};
+
+/// TAMA5 has 32 bytes of RAM of its own, regardless of the header's RAM size.
+pub const RAM_SIZE: usize = 0x20;
+
+// The registers selected by writing to 0xA001, each holding a nibble.
+const BANK_LOW: u8 = 0x0;
+const BANK_HIGH: u8 = 0x1;
+const DATA_LOW: u8 = 0x4;
+const DATA_HIGH: u8 = 0x5;
+const ADDRESS_HIGH: u8 = 0x6;
+const ADDRESS_LOW: u8 = 0x7;
+const READY: u8 = 0xA;
+const READ_LOW: u8 = 0xC;
+const READ_HIGH: u8 = 0xD;
+
+// Operations in bits 1-3 of ADDRESS_HIGH, carried out when ADDRESS_LOW is written.
+const WRITE_RAM: u8 = 0x0;
+const READ_RAM: u8 = 0x1;
+const COMMAND: u8 = 0x2;
+const CLOCK_REGISTER: u8 = 0x4;
+
+// The commands COMMAND runs, taken from the rest of the address. The minute and hour
+// are written from and read into the data registers as BCD.
+const STOP_CLOCK: u8 = 0x00;
+const START_CLOCK: u8 = 0x01;
+const WRITE_MINUTE: u8 = 0x04;
+const WRITE_HOUR: u8 = 0x05;
+const READ_MINUTE: u8 = 0x06;
+const READ_HOUR: u8 = 0x07;
+
+// The clock chip has four pages of 13 nibble registers. CLOCK_REGISTER reaches them
+// directly: ADDRESS_LOW is twice the page, plus one to read, DATA_LOW picks the
+// register and DATA_HIGH holds what is written.
+const PAGES: usize = 4;
+const PAGE_REGISTERS: usize = 13;
+const TIME_PAGE: usize = 0;
+const ALARM_PAGE: usize = 1;
+
+// Registers of the time page, holding BCD digits.
+const SECOND: usize = 0x0;
+const MINUTE: usize = 0x2;
+const HOUR: usize = 0x4;
+const WEEKDAY: usize = 0x6;
+const DAY: usize = 0x7;
+const MONTH: usize = 0x9;
+const YEAR: usize = 0xB;
+// Register of the alarm page counting years since the last leap year.
+const LEAP_YEAR: usize = 0xB;
+
+// With a two-digit year and a leap year every four, the calendar repeats every
+// century.
+const DAYS_PER_CENTURY: u64 = 100 * 365 + 25;
+
+// Save files end in a 64-bit Unix time, the clock's registers one nibble per byte,
+// and whether it is running.
+const SAVE_LENGTH: usize = 8 + PAGES * PAGE_REGISTERS + 1;
+
+/// Bandai's TAMA5, from the Tamagotchi game. Everything, ROM banking included, goes
+/// through a register select at 0xA001 and a nibble-wide data port at 0xA000. Behind
+/// it sits a clock chip with a calendar, which always counts hours from 0 to 23; its
+/// alarm is not emulated.
+pub struct Tama5 {
+    selected: u8,
+    registers: [u8; 0x10],
+    crystal: Crystal,
+    clock_running: bool,
+    clock: [[u8; PAGE_REGISTERS]; PAGES],
+}
+
+impl Tama5 {
+    pub fn new(clock: RtcClock) -> Self {
+        let mut time = [0; PAGE_REGISTERS];
+        // The calendar starts on the 1st of January.
+        time[DAY] = 1;
+        time[MONTH] = 1;
+        Tama5 {
+            selected: 0,
+            registers: [0; 0x10],
+            crystal: Crystal::new(clock),
+            clock_running: false,
+            clock: [
+                time,
+                [0; PAGE_REGISTERS],
+                [0; PAGE_REGISTERS],
+                [0; PAGE_REGISTERS],
+            ],
+        }
+    }
+
+    fn operation(&self) -> u8 {
+        self.registers[ADDRESS_HIGH as usize] >> 1
+    }
+
+    fn ram_address(&self) -> usize {
+        (((self.registers[ADDRESS_HIGH as usize] & 0b1) << 4)
+            | self.registers[ADDRESS_LOW as usize]) as usize
+    }
+
+    fn data(&self) -> u8 {
+        (self.registers[DATA_HIGH as usize] << 4) | self.registers[DATA_LOW as usize]
+    }
+
+    // The time page as a two-digit BCD number starting at `register`.
+    fn time(&self, register: usize) -> u64 {
+        let time = &self.clock[TIME_PAGE];
+        time[register] as u64 + 10 * time[register + 1] as u64
+    }
+
+    fn set_time(&mut self, register: usize, value: u64) {
+        let time = &mut self.clock[TIME_PAGE];
+        time[register] = (value % 10) as u8;
+        time[register + 1] = (value / 10 % 10) as u8;
+    }
+
+    fn sync(&mut self) {
+        let seconds = self.crystal.sync();
+        self.run(seconds);
+    }
+
+    fn run(&mut self, seconds: u64) {
+        if !self.clock_running || seconds == 0 {
+            return;
+        }
+        let seconds = self.time(SECOND) + seconds;
+        self.set_time(SECOND, seconds % 60);
+        let minutes = self.time(MINUTE) + seconds / 60;
+        self.set_time(MINUTE, minutes % 60);
+        let hours = self.time(HOUR) + minutes / 60;
+        self.set_time(HOUR, hours % 24);
+        let days = hours / 24;
+        let weekday = &mut self.clock[TIME_PAGE][WEEKDAY];
+        *weekday = ((*weekday as u64 + days) % 7) as u8;
+
+        // Whole centuries past the first change nothing, and the first puts any
+        // out-of-range date back in range.
+        let mut days = if days > DAYS_PER_CENTURY {
+            DAYS_PER_CENTURY + days % DAYS_PER_CENTURY
+        } else {
+            days
+        };
+        let (mut day, mut month, mut year) = (self.time(DAY), self.time(MONTH), self.time(YEAR));
+        let mut leap_year = self.clock[ALARM_PAGE][LEAP_YEAR] as u64 % 4;
+        // A month at a time, which the first step may leave partway through.
+        loop {
+            let left_in_month = days_in_month(month, leap_year == 0).saturating_sub(day) + 1;
+            if days < left_in_month {
+                day += days;
+                break;
+            }
+            days -= left_in_month;
+            day = 1;
+            month += 1;
+            if month > 12 {
+                month = 1;
+                year = (year + 1) % 100;
+                leap_year = (leap_year + 1) % 4;
+            }
+        }
+        self.set_time(DAY, day);
+        self.set_time(MONTH, month);
+        self.set_time(YEAR, year);
+        self.clock[ALARM_PAGE][LEAP_YEAR] = leap_year as u8;
+    }
+
+    // Runs the COMMAND given by the address registers. Returns whether the clock was
+    // changed.
+    fn execute(&mut self) -> bool {
+        self.sync();
+        match self.ram_address() as u8 {
+            STOP_CLOCK => self.clock_running = false,
+            START_CLOCK => {
+                self.clock_running = true;
+                self.set_time(SECOND, 0);
+                self.crystal.restart_second();
+            }
+            WRITE_MINUTE => self.set_time(MINUTE, bcd_to_binary(self.data()) % 60),
+            WRITE_HOUR => self.set_time(HOUR, bcd_to_binary(self.data()) % 24),
+            _ => return false,
+        }
+        true
+    }
+
+    // Writes a register of the clock chip if CLOCK_REGISTER is aimed at one. Returns
+    // whether it was.
+    fn write_clock_register(&mut self) -> bool {
+        let address = self.registers[ADDRESS_LOW as usize];
+        let register = self.registers[DATA_LOW as usize] as usize;
+        if address & 1 != 0 || register >= PAGE_REGISTERS {
+            return false;
+        }
+        self.sync();
+        self.clock[(address >> 1) as usize % PAGES][register] = self.registers[DATA_HIGH as usize];
+        true
+    }
+
+    // What a read of READ_LOW and READ_HIGH sees, as a byte.
+    fn read_result(&self, ram: &[u8]) -> u8 {
+        match self.operation() {
+            READ_RAM if !ram.is_empty() => ram[self.ram_address() % ram.len()],
+            COMMAND => match self.ram_address() as u8 {
+                READ_MINUTE => {
+                    self.clock[TIME_PAGE][MINUTE] | (self.clock[TIME_PAGE][MINUTE + 1] << 4)
+                }
+                READ_HOUR => self.clock[TIME_PAGE][HOUR] | (self.clock[TIME_PAGE][HOUR + 1] << 4),
+                _ => 0x00,
+            },
+            CLOCK_REGISTER => {
+                let address = self.registers[ADDRESS_LOW as usize];
+                let register = self.registers[DATA_LOW as usize] as usize;
+                if address & 1 == 0 || register >= PAGE_REGISTERS {
+                    return 0x00;
+                }
+                self.clock[(address >> 1) as usize % PAGES][register]
+            }
+            _ => 0x00,
+        }
+    }
+}
+
+fn bcd_to_binary(value: u8) -> u64 {
+    (value & 0x0F) as u64 + 10 * (value >> 4) as u64
+}
+
+fn days_in_month(month: u64, leap_year: bool) -> u64 {
+    match month {
+        2 if leap_year => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl Mbc for Tama5 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        read_rom_bank(rom, self.rom_bank(address), address)
+    }
+
+    fn write_register(&mut self, _address: u16, _value: u8) -> bool {
+        false
+    }
+
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        if address & 0x1FFF != 0 {
+            return 0xFF;
+        }
+        match self.selected {
+            // Tells the game the chip has woken up.
+            READY => 0xF1,
+            READ_LOW => 0xF0 | (self.read_result(ram) & 0x0F),
+            READ_HIGH => 0xF0 | (self.read_result(ram) >> 4),
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        match address & 0x1FFF {
+            0x0000 => {
+                let Some(register) = self.registers.get_mut(self.selected as usize) else {
+                    return false;
+                };
+                *register = value & 0x0F;
+                if self.selected != ADDRESS_LOW {
+                    return false;
+                }
+                match self.operation() {
+                    WRITE_RAM if !ram.is_empty() => {
+                        let offset = self.ram_address() % ram.len();
+                        ram[offset] = self.data();
+                        true
+                    }
+                    COMMAND => self.execute(),
+                    CLOCK_REGISTER => self.write_clock_register(),
+                    _ => false,
+                }
+            }
+            0x0001 => {
+                self.selected = value;
+                false
+            }
+            _ => false,
+        }
+    }
+
+    fn rom_bank(&self, address: u16) -> usize {
+        match address {
+            0x0000..=0x3FFF => 0,
+            _ => {
+                (self.registers[BANK_LOW as usize] | (self.registers[BANK_HIGH as usize] << 4))
+                    as usize
+            }
+        }
+    }
+
+    fn tick(&mut self, double_speed: bool) {
+        let seconds = self.crystal.tick(double_speed);
+        self.run(seconds);
+    }
+
+    fn battery_data(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(SAVE_LENGTH);
+        data.extend_from_slice(&unix_time().to_le_bytes());
+        data.extend(self.clock.iter().flatten());
+        data.push(self.clock_running as u8);
+        data
+    }
+
+    fn load_battery_data(&mut self, data: &[u8]) {
+        if data.len() != SAVE_LENGTH {
+            return;
+        }
+        let timestamp = u64::from_le_bytes(data[0..8].try_into().unwrap());
+        for (page, saved) in self.clock.iter_mut().zip(data[8..].chunks(PAGE_REGISTERS)) {
+            for (register, &nibble) in page.iter_mut().zip(saved) {
+                *register = nibble & 0x0F;
+            }
+        }
+        self.clock_running = data[SAVE_LENGTH - 1] != 0;
+        let seconds = self.crystal.seconds_since(timestamp);
+        self.run(seconds);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_register(mbc: &mut Tama5, ram: &mut [u8], register: u8, value: u8) {
+        mbc.write_ram(ram, 0xA001, register);
+        mbc.write_ram(ram, 0xA000, value);
+    }
+
+    #[test]
+    fn test_banks_and_ram_go_through_the_register_port() {
+        let rom: Vec<u8> = (0..32).flat_map(|bank| vec![bank as u8; 0x4000]).collect();
+        let mut ram = vec![0; RAM_SIZE];
+        let mut mbc = Tama5::new(RtcClock::Emulated);
+        write_register(&mut mbc, &mut ram, BANK_LOW, 0x3);
+        write_register(&mut mbc, &mut ram, BANK_HIGH, 0x1);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x13);
+
+        write_register(&mut mbc, &mut ram, DATA_LOW, 0x4);
+        write_register(&mut mbc, &mut ram, DATA_HIGH, 0xB);
+        write_register(&mut mbc, &mut ram, ADDRESS_HIGH, (WRITE_RAM << 1) | 1);
+        write_register(&mut mbc, &mut ram, ADDRESS_LOW, 0x2);
+        assert_eq!(ram[0x12], 0xB4);
+
+        write_register(&mut mbc, &mut ram, ADDRESS_HIGH, (READ_RAM << 1) | 1);
+        write_register(&mut mbc, &mut ram, ADDRESS_LOW, 0x2);
+        mbc.write_ram(&mut ram, 0xA001, READ_HIGH);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0xFB);
+        mbc.write_ram(&mut ram, 0xA001, READY);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0xF1);
+    }
+
+    #[test]
+    fn test_clock_keeps_time_through_commands_and_saves() {
+        let mut ram = vec![0; RAM_SIZE];
+        let mut mbc = Tama5::new(RtcClock::Emulated);
+        let command = |mbc: &mut Tama5, ram: &mut [u8], command: u8| {
+            write_register(mbc, ram, ADDRESS_HIGH, (COMMAND << 1) | (command >> 4));
+            write_register(mbc, ram, ADDRESS_LOW, command & 0x0F);
+        };
+        let read = |mbc: &mut Tama5, ram: &mut [u8]| {
+            mbc.write_ram(ram, 0xA001, READ_LOW);
+            let low = mbc.read_ram(ram, 0xA000) & 0x0F;
+            mbc.write_ram(ram, 0xA001, READ_HIGH);
+            low | (mbc.read_ram(ram, 0xA000) << 4)
+        };
+
+        // 23:59 on Wednesday the 28th of February in a leap year.
+        write_register(&mut mbc, &mut ram, DATA_LOW, 0x9);
+        write_register(&mut mbc, &mut ram, DATA_HIGH, 0x5);
+        command(&mut mbc, &mut ram, WRITE_MINUTE);
+        write_register(&mut mbc, &mut ram, DATA_LOW, 0x3);
+        write_register(&mut mbc, &mut ram, DATA_HIGH, 0x2);
+        command(&mut mbc, &mut ram, WRITE_HOUR);
+        for (register, value) in [(WEEKDAY, 3), (DAY, 8), (DAY + 1, 2), (MONTH, 2)] {
+            write_register(&mut mbc, &mut ram, DATA_LOW, register as u8);
+            write_register(&mut mbc, &mut ram, DATA_HIGH, value);
+            write_register(&mut mbc, &mut ram, ADDRESS_HIGH, CLOCK_REGISTER << 1);
+            write_register(&mut mbc, &mut ram, ADDRESS_LOW, 0x0);
+        }
+
+        // Time stands still until the clock is started.
+        mbc.run(60);
+        command(&mut mbc, &mut ram, READ_MINUTE);
+        assert_eq!(read(&mut mbc, &mut ram), 0x59);
+        command(&mut mbc, &mut ram, START_CLOCK);
+        mbc.run(60);
+        command(&mut mbc, &mut ram, READ_HOUR);
+        assert_eq!(read(&mut mbc, &mut ram), 0x00);
+        assert_eq!(mbc.clock[TIME_PAGE][DAY..DAY + 4], [9, 2, 2, 0]);
+        assert_eq!(mbc.clock[TIME_PAGE][WEEKDAY], 4);
+
+        write_register(&mut mbc, &mut ram, DATA_LOW, DAY as u8);
+        write_register(&mut mbc, &mut ram, ADDRESS_HIGH, CLOCK_REGISTER << 1);
+        write_register(&mut mbc, &mut ram, ADDRESS_LOW, 0x1);
+        assert_eq!(read(&mut mbc, &mut ram) & 0x0F, 9);
+
+        let mut restored = Tama5::new(RtcClock::Emulated);
+        restored.load_battery_data(&mbc.battery_data());
+        assert_eq!(restored.clock, mbc.clock);
+        assert!(restored.clock_running);
+
+        // A save from long ago is caught up on at once: 366 days after the 29th of
+        // February is the 1st of March of the next year, which is not a leap year.
+        restored.run(366 * 24 * 60 * 60 + 61);
+        assert_eq!(
+            restored.clock[TIME_PAGE][..DAY + 6],
+            [1, 0, 1, 0, 0, 0, 6, 1, 0, 3, 0, 1, 0]
+        );
+        assert_eq!(restored.clock[ALARM_PAGE][LEAP_YEAR], 1);
+    }
+}
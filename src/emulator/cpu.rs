@@ -1,14 +1,37 @@
+use crate::emulator::error::{EmulatorError, RegisterSnapshot};
 use crate::emulator::flags::FlagsRegister;
+use crate::emulator::interrupts::Interrupt;
 use crate::emulator::mmu::Mmu;
-use crate::emulator::ppu::Ppu;
+use crate::emulator::model::Model;
 
 const PREFIXED_OPCODE: u8 = 0xCB;
 
+/// What the CPU does when it fetches one of the undefined opcodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IllegalOpcodePolicy {
+    /// Stop emulation and report the opcode as an `EmulatorError`.
+    #[default]
+    Error,
+    /// Hang like real hardware: the CPU never executes another instruction and
+    /// interrupts can no longer wake it, while the rest of the system keeps running.
+    Lock,
+    /// Skip the opcode as if it were a one-byte NOP.
+    Nop,
+}
+
 pub struct Cpu {
     registers: Registers,
     pc: u16,
-    sp: u16,
     ime: bool,
+    // Set by EI: IME only becomes active once the following instruction has run.
+    ime_pending: bool,
+    halted: bool,
+    // HALT executed with IME=0 while an interrupt was already pending: the CPU does
+    // not halt, but fails to increment PC past the next opcode.
+    halt_bug: bool,
+    stopped: bool,
+    locked: bool,
+    illegal_opcode_policy: IllegalOpcodePolicy,
 }
 
 impl Cpu {
@@ -16,86 +39,255 @@ impl Cpu {
         Cpu {
             registers: Registers::new(),
             pc: 0x100,
-            sp: 0xFFFE,
             ime: false,
+            ime_pending: false,
+            halted: false,
+            halt_bug: false,
+            stopped: false,
+            locked: false,
+            illegal_opcode_policy: IllegalOpcodePolicy::default(),
+        }
+    }
+
+    /// Starts execution at 0x0000, leaving the boot ROM to set up every register.
+    pub fn start_boot_rom(&mut self) {
+        self.registers = Registers::new();
+        self.pc = 0x0000;
+    }
+
+    /// Loads the registers the boot ROM of `model` hands over to `rom` with.
+    pub fn set_post_boot_state(&mut self, model: Model, rom: &[u8]) {
+        let header = |address: usize| rom.get(address).copied().unwrap_or(0);
+        // The DMG and MGB boot ROMs leave H and C set unless the header checksum is 0.
+        let header_flags = if header(0x14D) == 0 { 0x80 } else { 0xB0 };
+        let (af, bc, de, hl) = match model {
+            Model::Dmg0 => (0x0100, 0xFF13, 0x00C1, 0x8403),
+            Model::Dmg => (0x0100 | header_flags, 0x0013, 0x00D8, 0x014D),
+            Model::Mgb => (0xFF00 | header_flags, 0x0013, 0x00D8, 0x014D),
+            Model::Sgb => (0x0100, 0x0014, 0x0000, 0xC060),
+            Model::Sgb2 => (0xFF00, 0x0014, 0x0000, 0xC060),
+            Model::Cgb | Model::Agb if header(0x143) & 0x80 != 0 => {
+                (0x1180, 0x0000, 0xFF56, 0x000D)
+            }
+            Model::Cgb | Model::Agb => {
+                // For DMG games the boot ROM hashes the title of Nintendo-licensed
+                // cartridges to pick a compatibility palette, leaving the hash in B.
+                let nintendo = header(0x14B) == 0x01
+                    || (header(0x14B) == 0x33 && header(0x144) == b'0' && header(0x145) == b'1');
+                let hash = if nintendo {
+                    (0x134..0x144).fold(0u8, |sum, address| sum.wrapping_add(header(address)))
+                } else {
+                    0
+                };
+                let hl = if hash == 0x43 || hash == 0x58 {
+                    0x991A
+                } else {
+                    0x007C
+                };
+                (0x1180, (hash as u16) << 8, 0x0008, hl)
+            }
+        };
+        self.registers.set_af(af);
+        self.registers.set_bc(bc);
+        self.registers.set_de(de);
+        self.registers.set_hl(hl);
+        self.registers.sp = 0xFFFE;
+        self.pc = 0x100;
+
+        if model == Model::Agb {
+            // The AGB boot ROM ends with an extra INC B, which also rewrites the flags.
+            self.registers.b = self.inc(self.registers.b);
         }
     }
 
-    pub fn step(&mut self, mmu: &mut Mmu, _ppu: &mut Ppu) {
+    pub fn set_illegal_opcode_policy(&mut self, policy: IllegalOpcodePolicy) {
+        self.illegal_opcode_policy = policy;
+    }
+
+    /// Executes a single instruction and returns the number of M-cycles it took.
+    ///
+    /// Every memory access ticks the rest of the system through the `Mmu`, so the
+    /// cost is measured from the bus rather than looked up per opcode.
+    pub fn step(&mut self, mmu: &mut Mmu) -> Result<u32, EmulatorError> {
+        let start = mmu.cycles;
+        if self.locked {
+            mmu.tick();
+            return Ok((mmu.cycles - start) as u32);
+        }
+
+        if self.stopped {
+            // STOP lasts until a joypad line goes low, which also requests the joypad
+            // interrupt whether or not it is enabled. Other interrupts do not end it.
+            // The bus keeps ticking so the rest of the system does not stall with it.
+            mmu.tick();
+            if mmu.interrupts.is_requested(Interrupt::Joypad) {
+                self.stopped = false;
+            }
+            return Ok((mmu.cycles - start) as u32);
+        }
+
+        // Interrupts are sampled at the end of the opcode fetch, so one raised during
+        // that cycle still preempts the instruction and the fetched byte is discarded.
         let mut instruction_byte = mmu.read_byte(self.pc);
+        if self.halted {
+            // A halted CPU keeps fetching the opcode after HALT while the rest of the
+            // system runs, and carries on with that fetch once an interrupt is pending.
+            if !mmu.interrupts.wakes_from_halt() {
+                return Ok((mmu.cycles - start) as u32);
+            }
+            self.halted = false;
+            mmu.set_cpu_halted(false);
+        }
+        if self.ime && mmu.interrupts.pending().is_some() {
+            self.dispatch_interrupt(mmu);
+            return Ok((mmu.cycles - start) as u32);
+        }
+        if self.halt_bug {
+            // Operands are read starting from the opcode itself, as PC never moved.
+            self.halt_bug = false;
+            self.pc = self.pc.wrapping_sub(1);
+        }
         let prefixed = instruction_byte == PREFIXED_OPCODE;
         if prefixed {
-            instruction_byte = mmu.read_byte(self.pc + 1);
+            instruction_byte = mmu.read_byte(self.pc.wrapping_add(1));
         }
 
         let instruction = match Instruction::from_byte(instruction_byte, prefixed) {
             Some(instruction) => instruction,
             None => {
-                println!("Unknown instruction: {:X}", instruction_byte);
-                return;
+                self.illegal_opcode(mmu, instruction_byte)?;
+                return Ok((mmu.cycles - start) as u32);
             }
         };
 
-        println!("Executing instruction: {:?}", instruction);
+        let enable_ime = self.ime_pending;
         self.pc = self.execute_instruction(mmu, instruction);
+        // A DI straight after EI clears the pending flag, so IME is never enabled.
+        if enable_ime && self.ime_pending {
+            self.ime = true;
+            self.ime_pending = false;
+        }
+        Ok((mmu.cycles - start) as u32)
+    }
+
+    // Every CB-prefixed byte is defined, so only unprefixed opcodes end up here.
+    fn illegal_opcode(&mut self, mmu: &Mmu, opcode: u8) -> Result<(), EmulatorError> {
+        match self.illegal_opcode_policy {
+            IllegalOpcodePolicy::Error => Err(EmulatorError::IllegalOpcode {
+                opcode,
+                pc: self.pc,
+                bank: mmu.rom_bank_at(self.pc),
+                registers: self.snapshot(),
+            }),
+            IllegalOpcodePolicy::Lock => {
+                self.locked = true;
+                Ok(())
+            }
+            IllegalOpcodePolicy::Nop => {
+                self.pc = self.pc.wrapping_add(1);
+                Ok(())
+            }
+        }
+    }
+
+    fn snapshot(&self) -> RegisterSnapshot {
+        RegisterSnapshot {
+            af: self.registers.get_af(),
+            bc: self.registers.get_bc(),
+            de: self.registers.get_de(),
+            hl: self.registers.get_hl(),
+            sp: self.registers.sp,
+            pc: self.pc,
+        }
+    }
+
+    // Interrupt dispatch takes 5 M-cycles: the discarded opcode fetch, a wait state,
+    // two writes pushing PC and a final cycle loading the vector. The interrupt to
+    // service is only chosen after the high byte of PC is pushed, so a push that
+    // overwrites IE can cancel it and leave the CPU jumping to 0x0000 instead.
+    fn dispatch_interrupt(&mut self, mmu: &mut Mmu) {
+        self.ime = false;
+        if self.halt_bug {
+            // EI followed by HALT with an interrupt pending: the interrupt returns to
+            // the HALT itself, which then executes again.
+            self.halt_bug = false;
+            self.pc = self.pc.wrapping_sub(1);
+        }
+        mmu.tick();
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        mmu.set_byte(self.registers.sp, (self.pc >> 8) as u8);
+        let interrupt = mmu.interrupts.pending();
+        if let Some(interrupt) = interrupt {
+            mmu.interrupts.acknowledge(interrupt);
+        }
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        mmu.set_byte(self.registers.sp, (self.pc & 0xFF) as u8);
+        self.pc = interrupt.map_or(0x0000, |interrupt| interrupt.vector());
+        mmu.tick();
     }
 
     fn execute_instruction(&mut self, mmu: &mut Mmu, instruction: Instruction) -> u16 {
         match instruction {
             Instruction::Nop() => self.pc.wrapping_add(1),
+            Instruction::Stop() => {
+                mmu.reset_divider();
+                if mmu.speed_switch_armed() {
+                    mmu.switch_speed();
+                } else {
+                    self.stopped = true;
+                }
+                self.pc.wrapping_add(2)
+            }
             Instruction::Halt() => {
-                println!("HALT not implemented");
+                if self.ime || mmu.interrupts.pending().is_none() {
+                    self.halted = true;
+                    mmu.set_cpu_halted(true);
+                } else {
+                    self.halt_bug = true;
+                }
                 self.pc.wrapping_add(1)
             }
-            Instruction::Return() => {
-                let low = mmu.read_byte(self.sp) as u16;
-                self.sp = self.sp.wrapping_add(1);
-                let high = mmu.read_byte(self.sp) as u16;
-                self.sp = self.sp.wrapping_add(1);
-                (high << 8) | low
-            }
-            Instruction::Load(target) => {
-                match target {
-                    Target::SP => {
-                        let most_significant_byte = mmu.read_byte(self.pc + 2) as u16;
-                        let least_significant_byte = mmu.read_byte(self.pc + 1) as u16;
-                        self.sp = (most_significant_byte << 8) | least_significant_byte;
-                    }
-                    _ => panic!("Unimplemented load target"),
-                }
+            Instruction::LoadN16(register) => {
+                let value = self.read_n16(mmu);
+                self.registers.write16(register, value);
                 self.pc.wrapping_add(3)
             }
-            Instruction::LoadN8(target) => {
-                match target {
-                    Target::B => {
-                        self.registers.b = mmu.read_byte(self.pc + 1);
-                    }
-                    Target::D => {
-                        self.registers.d = mmu.read_byte(self.pc + 1);
-                    }
-                    Target::H => {
-                        self.registers.h = mmu.read_byte(self.pc + 1);
-                    }
-                    Target::HL => {
-                        let val = mmu.read_byte(self.pc + 1);
-                        mmu.set_byte(self.registers.get_hl(), val);
-                    }
-                    Target::C => {
-                        self.registers.c = mmu.read_byte(self.pc + 1);
-                    }
-                    Target::E => {
-                        self.registers.e = mmu.read_byte(self.pc + 1);
-                    }
-                    Target::L => {
-                        self.registers.l = mmu.read_byte(self.pc + 1);
-                    }
-                    Target::A => {
-                        self.registers.a = mmu.read_byte(self.pc + 1);
-                    }
-                    _ => panic!("Unimplemented loadN8 target"),
-                }
+            Instruction::LoadN8(register) => {
+                let value = self.read_n8(mmu);
+                self.registers.write8(mmu, register, value);
                 self.pc.wrapping_add(2)
             }
+            Instruction::LoadRegister(destination, source) => {
+                let value = self.registers.read8(mmu, source);
+                self.registers.write8(mmu, destination, value);
+                self.pc.wrapping_add(1)
+            }
+            Instruction::LoadIntoIndirect(target) => {
+                let address = self.indirect_address(target);
+                mmu.set_byte(address, self.registers.a);
+                self.pc.wrapping_add(1)
+            }
+            Instruction::LoadFromIndirect(target) => {
+                let address = self.indirect_address(target);
+                self.registers.a = mmu.read_byte(address);
+                self.pc.wrapping_add(1)
+            }
+            Instruction::LoadIntoMemory() => {
+                let address = self.read_n16(mmu);
+                mmu.set_byte(address, self.registers.a);
+                self.pc.wrapping_add(3)
+            }
+            Instruction::LoadFromMemory() => {
+                let address = self.read_n16(mmu);
+                self.registers.a = mmu.read_byte(address);
+                self.pc.wrapping_add(3)
+            }
+            Instruction::LoadSpIntoMemory() => {
+                let address = self.read_n16(mmu);
+                mmu.set_byte(address, (self.registers.sp & 0xFF) as u8);
+                mmu.set_byte(address.wrapping_add(1), (self.registers.sp >> 8) as u8);
+                self.pc.wrapping_add(3)
+            }
             Instruction::LoadHC() => {
                 mmu.set_byte(0xFF00 + self.registers.c as u16, self.registers.a);
                 self.pc.wrapping_add(1)
@@ -104,343 +296,285 @@ impl Cpu {
                 self.registers.a = mmu.read_byte(0xFF00 + self.registers.c as u16);
                 self.pc.wrapping_add(1)
             }
-            Instruction::Inc(target) => {
-                match target {
-                    Target::BC => {
-                        let value = self.registers.get_bc();
-                        self.registers.set_bc(value.wrapping_add(1));
-                    }
-                    Target::DE => {
-                        let value = self.registers.get_de();
-                        self.registers.set_de(value.wrapping_add(1));
-                    }
-                    Target::HL => {
-                        let value = self.registers.get_hl();
-                        self.registers.set_hl(value.wrapping_add(1));
-                    }
-                    Target::SP => {
-                        self.sp = self.sp.wrapping_add(1);
-                    }
-                    Target::A => {
-                        let value = self.registers.a;
-                        self.registers.a = self.registers.a.wrapping_add(1);
-                        self.registers.f.zero = self.registers.a == 0;
-                        self.registers.f.subtract = false;
-                        self.registers.f.half_carry = (value & 0xF) + 1 > 0xF;
-                    }
-                    Target::B => {
-                        let value = self.registers.b;
-                        self.registers.b = self.registers.b.wrapping_add(1);
-                        self.registers.f.zero = self.registers.b == 0;
-                        self.registers.f.subtract = false;
-                        self.registers.f.half_carry = (value & 0xF) + 1 > 0xF;
-                    }
-                    Target::D => {
-                        let value = self.registers.d;
-                        self.registers.d = self.registers.d.wrapping_add(1);
-                        self.registers.f.zero = self.registers.d == 0;
-                        self.registers.f.subtract = false;
-                        self.registers.f.half_carry = (value & 0xF) + 1 > 0xF;
-                    }
-                    Target::H => {
-                        let value = self.registers.h;
-                        self.registers.h = self.registers.h.wrapping_add(1);
-                        self.registers.f.zero = self.registers.h == 0;
-                        self.registers.f.subtract = false;
-                        self.registers.f.half_carry = (value & 0xF) + 1 > 0xF;
-                    }
-                    _ => panic!("Unimplemented inc target"),
-                }
+            Instruction::LoadHN8() => {
+                let offset = self.read_n8(mmu);
+                mmu.set_byte(0xFF00 + offset as u16, self.registers.a);
+                self.pc.wrapping_add(2)
+            }
+            Instruction::LoadHAN8() => {
+                let offset = self.read_n8(mmu);
+                self.registers.a = mmu.read_byte(0xFF00 + offset as u16);
+                self.pc.wrapping_add(2)
+            }
+            Instruction::LoadSpHl() => {
+                self.registers.sp = self.registers.get_hl();
+                mmu.tick();
                 self.pc.wrapping_add(1)
             }
-            Instruction::IncHl() => {
-                let value = self.registers.get_hl();
-                self.registers.set_hl(value.wrapping_add(1));
+            Instruction::LoadHlSpN8() => {
+                let offset = self.read_n8(mmu);
+                let value = self.add_sp_offset(offset);
+                self.registers.set_hl(value);
+                mmu.tick();
+                self.pc.wrapping_add(2)
+            }
+            Instruction::Push(register) => {
+                let value = self.registers.read16(register);
+                self.push(mmu, value);
                 self.pc.wrapping_add(1)
             }
-            Instruction::Add(target) => {
-                match target {
-                    Target::A => {
-                        self.add(self.registers.a);
-                    }
-                    Target::B => {
-                        self.add(self.registers.b);
-                    }
-                    Target::C => {
-                        self.add(self.registers.c);
-                    }
-                    Target::D => {
-                        self.add(self.registers.d);
+            Instruction::Pop(register) => {
+                let value = self.pop(mmu);
+                self.registers.write16(register, value);
+                self.pc.wrapping_add(1)
+            }
+            Instruction::Inc(register) => {
+                let value = self.registers.read8(mmu, register);
+                let result = self.inc(value);
+                self.registers.write8(mmu, register, result);
+                self.pc.wrapping_add(1)
+            }
+            Instruction::Dec(register) => {
+                let value = self.registers.read8(mmu, register);
+                let result = self.dec(value);
+                self.registers.write8(mmu, register, result);
+                self.pc.wrapping_add(1)
+            }
+            Instruction::Inc16(register) => {
+                let value = self.registers.read16(register);
+                self.registers.write16(register, value.wrapping_add(1));
+                mmu.tick();
+                self.pc.wrapping_add(1)
+            }
+            Instruction::Dec16(register) => {
+                let value = self.registers.read16(register);
+                self.registers.write16(register, value.wrapping_sub(1));
+                mmu.tick();
+                self.pc.wrapping_add(1)
+            }
+            Instruction::Alu(operation, register) => {
+                let value = self.registers.read8(mmu, register);
+                self.alu(operation, value);
+                self.pc.wrapping_add(1)
+            }
+            Instruction::AluN8(operation) => {
+                let value = self.read_n8(mmu);
+                self.alu(operation, value);
+                self.pc.wrapping_add(2)
+            }
+            Instruction::AddHl(register) => {
+                let hl = self.registers.get_hl();
+                let value = self.registers.read16(register);
+                let (new_value, did_overflow) = hl.overflowing_add(value);
+                self.registers.f.subtract = false;
+                self.registers.f.carry = did_overflow;
+                // Half Carry for 16-bit additions is taken from bit 11 rather than bit 3.
+                self.registers.f.half_carry = (hl & 0xFFF) + (value & 0xFFF) > 0xFFF;
+                self.registers.set_hl(new_value);
+                mmu.tick();
+                self.pc.wrapping_add(1)
+            }
+            Instruction::AddSp() => {
+                let offset = self.read_n8(mmu);
+                self.registers.sp = self.add_sp_offset(offset);
+                mmu.tick();
+                mmu.tick();
+                self.pc.wrapping_add(2)
+            }
+            Instruction::DecimalAdjust() => {
+                let mut a = self.registers.a;
+                let mut carry = self.registers.f.carry;
+                if self.registers.f.subtract {
+                    if self.registers.f.carry {
+                        a = a.wrapping_sub(0x60);
                     }
-                    Target::E => {
-                        self.add(self.registers.e);
+                    if self.registers.f.half_carry {
+                        a = a.wrapping_sub(0x06);
                     }
-                    Target::H => {
-                        self.add(self.registers.h);
+                } else {
+                    if self.registers.f.carry || a > 0x99 {
+                        a = a.wrapping_add(0x60);
+                        carry = true;
                     }
-                    Target::L => {
-                        self.add(self.registers.l);
+                    if self.registers.f.half_carry || (a & 0x0F) > 0x09 {
+                        a = a.wrapping_add(0x06);
                     }
-                    _ => panic!("Unimplemented add target"),
                 }
+                self.registers.a = a;
+                self.registers.f.zero = a == 0;
+                self.registers.f.half_carry = false;
+                self.registers.f.carry = carry;
                 self.pc.wrapping_add(1)
             }
-            Instruction::AddHl() => {
-                let hl = self.registers.get_hl();
-                let (new_value, did_overflow) = hl.overflowing_add(hl);
-                self.registers.f.zero = new_value == 0;
+            Instruction::Complement() => {
+                self.registers.a = !self.registers.a;
+                self.registers.f.subtract = true;
+                self.registers.f.half_carry = true;
+                self.pc.wrapping_add(1)
+            }
+            Instruction::SetCarry() => {
                 self.registers.f.subtract = false;
-                self.registers.f.carry = did_overflow;
-                self.registers.f.half_carry = (hl & 0xFFF) + (hl & 0xFFF) > 0xFFF;
-                self.registers.set_hl(new_value);
+                self.registers.f.half_carry = false;
+                self.registers.f.carry = true;
+                self.pc.wrapping_add(1)
+            }
+            Instruction::ComplementCarry() => {
+                self.registers.f.subtract = false;
+                self.registers.f.half_carry = false;
+                self.registers.f.carry = !self.registers.f.carry;
                 self.pc.wrapping_add(1)
             }
             Instruction::Jump(test) => {
-                let should_jump = match test {
-                    JumpTest::NotZero => !self.registers.f.zero,
-                    JumpTest::Zero => self.registers.f.zero,
-                    JumpTest::NotCarry => !self.registers.f.carry,
-                    JumpTest::Carry => self.registers.f.carry,
-                    JumpTest::Always => true,
-                };
-
-                if !should_jump {
+                let address = self.read_n16(mmu);
+                if !self.should_jump(test) {
                     return self.pc.wrapping_add(3);
                 }
 
-                let most_significant_byte = mmu.read_byte(self.pc + 2) as u16;
-                let least_significant_byte = mmu.read_byte(self.pc + 1) as u16;
-                (most_significant_byte << 8) | least_significant_byte
+                mmu.tick();
+                address
             }
             Instruction::JumpHl() => self.registers.get_hl(),
-            Instruction::RotateLeftCircular(target) => {
-                match target {
-                    Target::A => {
-                        let msb = (self.registers.a & 0b1000_0000) >> 7;
-                        self.registers.a = (self.registers.a << 1) | msb;
-                        self.registers.f.carry = msb == 1;
-                    }
-                    Target::B => {
-                        let msb = (self.registers.b & 0b1000_0000) >> 7;
-                        self.registers.b = (self.registers.b << 1) | msb;
-                        self.registers.f.carry = msb == 1;
-                        self.registers.f.zero = self.registers.b == 0;
-                    }
-                    Target::C => {
-                        let msb = (self.registers.c & 0b1000_0000) >> 7;
-                        self.registers.c = (self.registers.c << 1) | msb;
-                        self.registers.f.carry = msb == 1;
-                        self.registers.f.zero = self.registers.c == 0;
-                    }
-                    Target::D => {
-                        let msb = (self.registers.d & 0b1000_0000) >> 7;
-                        self.registers.d = (self.registers.d << 1) | msb;
-                        self.registers.f.carry = msb == 1;
-                        self.registers.f.zero = self.registers.d == 0;
-                    }
-                    Target::E => {
-                        let msb = (self.registers.e & 0b1000_0000) >> 7;
-                        self.registers.e = (self.registers.e << 1) | msb;
-                        self.registers.f.carry = msb == 1;
-                        self.registers.f.zero = self.registers.e == 0;
-                    }
-                    Target::H => {
-                        let msb = (self.registers.h & 0b1000_0000) >> 7;
-                        self.registers.h = (self.registers.h << 1) | msb;
-                        self.registers.f.carry = msb == 1;
-                        self.registers.f.zero = self.registers.h == 0;
-                    }
-                    Target::L => {
-                        let msb = (self.registers.l & 0b1000_0000) >> 7;
-                        self.registers.l = (self.registers.l << 1) | msb;
-                        self.registers.f.carry = msb == 1;
-                        self.registers.f.zero = self.registers.l == 0;
-                    }
-                    _ => panic!("Unimplemented rotate left circular target"),
+            Instruction::JumpRelative(test) => {
+                let offset = self.read_n8(mmu) as i8;
+                let next_pc = self.pc.wrapping_add(2);
+                if !self.should_jump(test) {
+                    return next_pc;
                 }
-                self.pc.wrapping_add(1)
+
+                mmu.tick();
+                next_pc.wrapping_add(offset as u16)
             }
-            Instruction::RotateRightCircular(target) => {
-                match target {
-                    Target::A => {
-                        let lsb = self.registers.a & 0b0000_0001;
-                        self.registers.a >>= 1;
-                        self.registers.a |= lsb << 7;
-                        self.registers.f.carry = lsb == 1;
-                    }
-                    Target::B => {
-                        let lsb = self.registers.b & 0b0000_0001;
-                        self.registers.b >>= 1;
-                        self.registers.b |= lsb << 7;
-                        self.registers.f.carry = lsb == 1;
-                        self.registers.f.zero = self.registers.b == 0;
-                    }
-                    Target::C => {
-                        let lsb = self.registers.c & 0b0000_0001;
-                        self.registers.c >>= 1;
-                        self.registers.c |= lsb << 7;
-                        self.registers.f.carry = lsb == 1;
-                        self.registers.f.zero = self.registers.c == 0;
-                    }
-                    Target::D => {
-                        let lsb = self.registers.d & 0b0000_0001;
-                        self.registers.d >>= 1;
-                        self.registers.d |= lsb << 7;
-                        self.registers.f.carry = lsb == 1;
-                        self.registers.f.zero = self.registers.d == 0;
-                    }
-                    Target::E => {
-                        let lsb = self.registers.e & 0b0000_0001;
-                        self.registers.e >>= 1;
-                        self.registers.e |= lsb << 7;
-                        self.registers.f.carry = lsb == 1;
-                        self.registers.f.zero = self.registers.e == 0;
-                    }
-                    Target::H => {
-                        let lsb = self.registers.h & 0b0000_0001;
-                        self.registers.h >>= 1;
-                        self.registers.h |= lsb << 7;
-                        self.registers.f.carry = lsb == 1;
-                        self.registers.f.zero = self.registers.h == 0;
-                    }
-                    Target::L => {
-                        let lsb = self.registers.l & 0b0000_0001;
-                        self.registers.l >>= 1;
-                        self.registers.l |= lsb << 7;
-                        self.registers.f.carry = lsb == 1;
-                        self.registers.f.zero = self.registers.l == 0;
-                    }
-                    _ => panic!("Unimplemented rotate right circular target"),
+            Instruction::Call(test) => {
+                let address = self.read_n16(mmu);
+                let next_pc = self.pc.wrapping_add(3);
+                if !self.should_jump(test) {
+                    return next_pc;
                 }
-                self.pc.wrapping_add(1)
+
+                self.push(mmu, next_pc);
+                address
             }
-            Instruction::RotateLeft(target) => {
-                match target {
-                    Target::A => {
-                        let msb = (self.registers.a & 0b1000_0000) >> 7;
-                        self.registers.a = (self.registers.a << 1) | self.registers.f.carry as u8;
-                        self.registers.f.carry = msb == 1;
-                    }
-                    Target::B => {
-                        let msb = (self.registers.b & 0b1000_0000) >> 7;
-                        self.registers.b = (self.registers.b << 1) | self.registers.f.carry as u8;
-                        self.registers.f.carry = msb == 1;
-                        self.registers.f.zero = self.registers.b == 0;
-                    }
-                    Target::C => {
-                        let msb = (self.registers.c & 0b1000_0000) >> 7;
-                        self.registers.c = (self.registers.c << 1) | self.registers.f.carry as u8;
-                        self.registers.f.carry = msb == 1;
-                        self.registers.f.zero = self.registers.c == 0;
-                    }
-                    Target::D => {
-                        let msb = (self.registers.d & 0b1000_0000) >> 7;
-                        self.registers.d = (self.registers.d << 1) | self.registers.f.carry as u8;
-                        self.registers.f.carry = msb == 1;
-                        self.registers.f.zero = self.registers.d == 0;
-                    }
-                    Target::E => {
-                        let msb = (self.registers.e & 0b1000_0000) >> 7;
-                        self.registers.e = (self.registers.e << 1) | self.registers.f.carry as u8;
-                        self.registers.f.carry = msb == 1;
-                        self.registers.f.zero = self.registers.e == 0;
-                    }
-                    Target::H => {
-                        let msb = (self.registers.h & 0b1000_0000) >> 7;
-                        self.registers.h = (self.registers.h << 1) | self.registers.f.carry as u8;
-                        self.registers.f.carry = msb == 1;
-                        self.registers.f.zero = self.registers.h == 0;
-                    }
-                    Target::L => {
-                        let msb = (self.registers.l & 0b1000_0000) >> 7;
-                        self.registers.l = (self.registers.l << 1) | self.registers.f.carry as u8;
-                        self.registers.f.carry = msb == 1;
-                        self.registers.f.zero = self.registers.l == 0;
-                    }
-                    _ => panic!("Unimplemented rotate left target"),
+            Instruction::Return(test) => {
+                // Conditional returns spend an extra cycle evaluating the condition.
+                if !matches!(test, JumpTest::Always) {
+                    mmu.tick();
                 }
-                self.pc.wrapping_add(1)
-            }
-            Instruction::RotateRight(target) => {
-                match target {
-                    Target::A => {
-                        let lsb = self.registers.a & 0b0000_0001;
-                        self.registers.a >>= 1;
-                        self.registers.a |= (self.registers.f.carry as u8) << 7;
-                        self.registers.f.carry = lsb == 1;
-                    }
-                    Target::B => {
-                        let lsb = self.registers.b & 0b0000_0001;
-                        self.registers.b >>= 1;
-                        self.registers.b |= (self.registers.f.carry as u8) << 7;
-                        self.registers.f.carry = lsb == 1;
-                        self.registers.f.zero = self.registers.b == 0;
-                    }
-                    Target::C => {
-                        let lsb = self.registers.c & 0b0000_0001;
-                        self.registers.c >>= 1;
-                        self.registers.c |= (self.registers.f.carry as u8) << 7;
-                        self.registers.f.carry = lsb == 1;
-                        self.registers.f.zero = self.registers.c == 0;
-                    }
-                    Target::D => {
-                        let lsb = self.registers.d & 0b0000_0001;
-                        self.registers.d >>= 1;
-                        self.registers.d |= (self.registers.f.carry as u8) << 7;
-                        self.registers.f.carry = lsb == 1;
-                        self.registers.f.zero = self.registers.d == 0;
-                    }
-                    Target::E => {
-                        let lsb = self.registers.e & 0b0000_0001;
-                        self.registers.e >>= 1;
-                        self.registers.e |= (self.registers.f.carry as u8) << 7;
-                        self.registers.f.carry = lsb == 1;
-                        self.registers.f.zero = self.registers.e == 0;
-                    }
-                    Target::H => {
-                        let lsb = self.registers.h & 0b0000_0001;
-                        self.registers.h >>= 1;
-                        self.registers.h |= (self.registers.f.carry as u8) << 7;
-                        self.registers.f.carry = lsb == 1;
-                        self.registers.f.zero = self.registers.h == 0;
-                    }
-                    Target::L => {
-                        let lsb = self.registers.l & 0b0000_0001;
-                        self.registers.l >>= 1;
-                        self.registers.l |= (self.registers.f.carry as u8) << 7;
-                        self.registers.f.carry = lsb == 1;
-                        self.registers.f.zero = self.registers.l == 0;
-                    }
-                    _ => panic!("Unimplemented rotate right target"),
+                if !self.should_jump(test) {
+                    return self.pc.wrapping_add(1);
                 }
+
+                let address = self.pop(mmu);
+                mmu.tick();
+                address
+            }
+            Instruction::ReturnInterrupt() => {
+                // Unlike EI, RETI enables interrupts immediately.
+                self.ime = true;
+                let address = self.pop(mmu);
+                mmu.tick();
+                address
+            }
+            Instruction::RotateA(operation) => {
+                // RLCA, RRCA, RLA and RRA always clear Z, unlike their CB-prefixed forms.
+                self.registers.a = self.shift(operation, self.registers.a);
+                self.registers.f.zero = false;
                 self.pc.wrapping_add(1)
             }
-            Instruction::LoadIntoMemory() => {
-                let low = mmu.read_byte(self.pc + 1) as u16;
-                let high = mmu.read_byte(self.pc + 2) as u16;
-                let address = (high << 8) | low;
-                mmu.set_byte(address, self.registers.a);
-                self.pc.wrapping_add(3)
+            Instruction::Shift(operation, register) => {
+                let value = self.registers.read8(mmu, register);
+                let result = self.shift(operation, value);
+                self.registers.write8(mmu, register, result);
+                self.pc.wrapping_add(2)
+            }
+            Instruction::Bit(bit, register) => {
+                let value = self.registers.read8(mmu, register);
+                self.registers.f.zero = value & (1 << bit) == 0;
+                self.registers.f.subtract = false;
+                self.registers.f.half_carry = true;
+                self.pc.wrapping_add(2)
+            }
+            Instruction::Reset(bit, register) => {
+                let value = self.registers.read8(mmu, register);
+                self.registers.write8(mmu, register, value & !(1 << bit));
+                self.pc.wrapping_add(2)
+            }
+            Instruction::Set(bit, register) => {
+                let value = self.registers.read8(mmu, register);
+                self.registers.write8(mmu, register, value | (1 << bit));
+                self.pc.wrapping_add(2)
             }
             Instruction::DisableInterrupt() => {
                 self.ime = false;
+                self.ime_pending = false;
                 self.pc.wrapping_add(1)
             }
             Instruction::EnableInterrupts() => {
-                self.ime = true;
+                if !self.ime {
+                    self.ime_pending = true;
+                }
                 self.pc.wrapping_add(1)
             }
             Instruction::Restart(address) => {
-                let return_pc = self.pc.wrapping_add(1);
-                self.sp = self.sp.wrapping_sub(1);
-                mmu.set_byte(self.sp, (return_pc >> 8) as u8);
-                self.sp = self.sp.wrapping_sub(1);
-                mmu.set_byte(self.sp, (return_pc & 0xFF) as u8);
+                self.push(mmu, self.pc.wrapping_add(1));
+                address
+            }
+        }
+    }
+
+    fn read_n8(&self, mmu: &mut Mmu) -> u8 {
+        mmu.read_byte(self.pc.wrapping_add(1))
+    }
+
+    fn read_n16(&self, mmu: &mut Mmu) -> u16 {
+        let least_significant_byte = mmu.read_byte(self.pc.wrapping_add(1)) as u16;
+        let most_significant_byte = mmu.read_byte(self.pc.wrapping_add(2)) as u16;
+        (most_significant_byte << 8) | least_significant_byte
+    }
+
+    fn indirect_address(&mut self, target: IndirectTarget) -> u16 {
+        match target {
+            IndirectTarget::BC => self.registers.get_bc(),
+            IndirectTarget::DE => self.registers.get_de(),
+            IndirectTarget::HLIncrement => {
+                let address = self.registers.get_hl();
+                self.registers.set_hl(address.wrapping_add(1));
+                address
+            }
+            IndirectTarget::HLDecrement => {
+                let address = self.registers.get_hl();
+                self.registers.set_hl(address.wrapping_sub(1));
                 address
             }
         }
     }
 
+    fn should_jump(&self, test: JumpTest) -> bool {
+        match test {
+            JumpTest::NotZero => !self.registers.f.zero,
+            JumpTest::Zero => self.registers.f.zero,
+            JumpTest::NotCarry => !self.registers.f.carry,
+            JumpTest::Carry => self.registers.f.carry,
+            JumpTest::Always => true,
+        }
+    }
+
+    fn push(&mut self, mmu: &mut Mmu, value: u16) {
+        // SP is decremented during an internal cycle before the two writes.
+        mmu.tick();
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        mmu.set_byte(self.registers.sp, (value >> 8) as u8);
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        mmu.set_byte(self.registers.sp, (value & 0xFF) as u8);
+    }
+
+    fn pop(&mut self, mmu: &mut Mmu) -> u16 {
+        let low = mmu.read_byte(self.registers.sp) as u16;
+        self.registers.sp = self.registers.sp.wrapping_add(1);
+        let high = mmu.read_byte(self.registers.sp) as u16;
+        self.registers.sp = self.registers.sp.wrapping_add(1);
+        (high << 8) | low
+    }
+
     fn add(&mut self, value: u8) {
         let (new_value, did_overflow) = self.registers.a.overflowing_add(value);
         self.registers.f.zero = new_value == 0;
@@ -452,9 +586,182 @@ impl Cpu {
         self.registers.f.half_carry = (self.registers.a & 0xF) + (value & 0xF) > 0xF;
         self.registers.a = new_value;
     }
+
+    fn adc(&mut self, value: u8) {
+        let carry = self.registers.f.carry as u8;
+        let result = self.registers.a as u16 + value as u16 + carry as u16;
+        self.registers.f.zero = result & 0xFF == 0;
+        self.registers.f.subtract = false;
+        self.registers.f.carry = result > 0xFF;
+        self.registers.f.half_carry = (self.registers.a & 0xF) + (value & 0xF) + carry > 0xF;
+        self.registers.a = result as u8;
+    }
+
+    fn sub(&mut self, value: u8) {
+        self.registers.a = self.compare(value, false);
+    }
+
+    fn sbc(&mut self, value: u8) {
+        self.registers.a = self.compare(value, self.registers.f.carry);
+    }
+
+    fn cp(&mut self, value: u8) {
+        self.compare(value, false);
+    }
+
+    // Shared by SUB, SBC and CP: subtracts value (and the borrow) from register A,
+    // updates the flags and returns the result without storing it.
+    fn compare(&mut self, value: u8, borrow: bool) -> u8 {
+        let borrow = borrow as u8;
+        let result = self.registers.a.wrapping_sub(value).wrapping_sub(borrow);
+        self.registers.f.zero = result == 0;
+        self.registers.f.subtract = true;
+        self.registers.f.carry = (self.registers.a as u16) < value as u16 + borrow as u16;
+        self.registers.f.half_carry = (self.registers.a & 0xF) < (value & 0xF) + borrow;
+        result
+    }
+
+    fn and(&mut self, value: u8) {
+        self.registers.a &= value;
+        self.registers.f = FlagsRegister {
+            zero: self.registers.a == 0,
+            subtract: false,
+            half_carry: true,
+            carry: false,
+        };
+    }
+
+    fn xor(&mut self, value: u8) {
+        self.registers.a ^= value;
+        self.registers.f = FlagsRegister {
+            zero: self.registers.a == 0,
+            ..FlagsRegister::default()
+        };
+    }
+
+    fn or(&mut self, value: u8) {
+        self.registers.a |= value;
+        self.registers.f = FlagsRegister {
+            zero: self.registers.a == 0,
+            ..FlagsRegister::default()
+        };
+    }
+
+    fn inc(&mut self, value: u8) -> u8 {
+        let result = value.wrapping_add(1);
+        self.registers.f.zero = result == 0;
+        self.registers.f.subtract = false;
+        self.registers.f.half_carry = (value & 0xF) + 1 > 0xF;
+        result
+    }
+
+    fn dec(&mut self, value: u8) -> u8 {
+        let result = value.wrapping_sub(1);
+        self.registers.f.zero = result == 0;
+        self.registers.f.subtract = true;
+        self.registers.f.half_carry = value & 0xF == 0;
+        result
+    }
+
+    // ADD SP,e8 and LD HL,SP+e8 take their carries from the unsigned addition of the
+    // low byte of SP and the offset, even though the offset is applied signed.
+    fn add_sp_offset(&mut self, offset: u8) -> u16 {
+        let sp = self.registers.sp;
+        self.registers.f = FlagsRegister {
+            zero: false,
+            subtract: false,
+            half_carry: (sp & 0xF) + (offset as u16 & 0xF) > 0xF,
+            carry: (sp & 0xFF) + offset as u16 > 0xFF,
+        };
+        sp.wrapping_add(offset as i8 as u16)
+    }
+
+    fn alu(&mut self, operation: AluOperation, value: u8) {
+        match operation {
+            AluOperation::Add => self.add(value),
+            AluOperation::Adc => self.adc(value),
+            AluOperation::Sub => self.sub(value),
+            AluOperation::Sbc => self.sbc(value),
+            AluOperation::And => self.and(value),
+            AluOperation::Xor => self.xor(value),
+            AluOperation::Or => self.or(value),
+            AluOperation::Cp => self.cp(value),
+        }
+    }
+
+    fn shift(&mut self, operation: ShiftOperation, value: u8) -> u8 {
+        match operation {
+            ShiftOperation::RotateLeftCircular => self.rotate_left_circular(value),
+            ShiftOperation::RotateRightCircular => self.rotate_right_circular(value),
+            ShiftOperation::RotateLeft => self.rotate_left(value),
+            ShiftOperation::RotateRight => self.rotate_right(value),
+            ShiftOperation::ShiftLeftArithmetic => self.shift_left_arithmetic(value),
+            ShiftOperation::ShiftRightArithmetic => self.shift_right_arithmetic(value),
+            ShiftOperation::Swap => self.swap(value),
+            ShiftOperation::ShiftRightLogical => self.shift_right_logical(value),
+        }
+    }
+
+    fn rotate_left_circular(&mut self, value: u8) -> u8 {
+        let result = value.rotate_left(1);
+        self.set_shift_flags(result, value & 0b1000_0000 != 0);
+        result
+    }
+
+    fn rotate_right_circular(&mut self, value: u8) -> u8 {
+        let result = value.rotate_right(1);
+        self.set_shift_flags(result, value & 0b0000_0001 != 0);
+        result
+    }
+
+    fn rotate_left(&mut self, value: u8) -> u8 {
+        let result = (value << 1) | self.registers.f.carry as u8;
+        self.set_shift_flags(result, value & 0b1000_0000 != 0);
+        result
+    }
+
+    fn rotate_right(&mut self, value: u8) -> u8 {
+        let result = (value >> 1) | ((self.registers.f.carry as u8) << 7);
+        self.set_shift_flags(result, value & 0b0000_0001 != 0);
+        result
+    }
+
+    fn shift_left_arithmetic(&mut self, value: u8) -> u8 {
+        let result = value << 1;
+        self.set_shift_flags(result, value & 0b1000_0000 != 0);
+        result
+    }
+
+    // SRA keeps bit 7 in place so the sign of the value is preserved.
+    fn shift_right_arithmetic(&mut self, value: u8) -> u8 {
+        let result = (value >> 1) | (value & 0b1000_0000);
+        self.set_shift_flags(result, value & 0b0000_0001 != 0);
+        result
+    }
+
+    fn swap(&mut self, value: u8) -> u8 {
+        let result = value.rotate_left(4);
+        self.set_shift_flags(result, false);
+        result
+    }
+
+    fn shift_right_logical(&mut self, value: u8) -> u8 {
+        let result = value >> 1;
+        self.set_shift_flags(result, value & 0b0000_0001 != 0);
+        result
+    }
+
+    fn set_shift_flags(&mut self, result: u8, carry: bool) {
+        self.registers.f = FlagsRegister {
+            zero: result == 0,
+            subtract: false,
+            half_carry: false,
+            carry,
+        };
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 enum JumpTest {
     NotZero,
     Zero,
@@ -463,136 +770,268 @@ enum JumpTest {
     Always,
 }
 
-#[derive(Debug)]
-enum Target {
-    BC,
-    DE,
-    HL,
-    SP,
-    A,
+/// An 8-bit operand, in the order the opcode's 3-bit register fields encode them.
+/// `HLIndirect` is the byte in memory that HL points at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Register8 {
     B,
     C,
     D,
     E,
     H,
     L,
+    HLIndirect,
+    A,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Register16 {
+    AF,
+    BC,
+    DE,
+    HL,
+    SP,
 }
 
+#[derive(Debug, Clone, Copy)]
+enum IndirectTarget {
+    BC,
+    DE,
+    HLIncrement,
+    HLDecrement,
+}
+
+/// The accumulator operations selected by bits 3-5 of 0x80-0xBF and 0xC6-0xFE.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AluOperation {
+    Add,
+    Adc,
+    Sub,
+    Sbc,
+    And,
+    Xor,
+    Or,
+    Cp,
+}
+
+/// The rotates and shifts selected by bits 3-5 of CB 0x00-0x3F.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ShiftOperation {
+    RotateLeftCircular,
+    RotateRightCircular,
+    RotateLeft,
+    RotateRight,
+    ShiftLeftArithmetic,
+    ShiftRightArithmetic,
+    Swap,
+    ShiftRightLogical,
+}
+
+const REGISTERS: [Register8; 8] = [
+    Register8::B,
+    Register8::C,
+    Register8::D,
+    Register8::E,
+    Register8::H,
+    Register8::L,
+    Register8::HLIndirect,
+    Register8::A,
+];
+
+// Register pairs as encoded by 16-bit loads and arithmetic, and by PUSH/POP.
+const REGISTER_PAIRS: [Register16; 4] = [
+    Register16::BC,
+    Register16::DE,
+    Register16::HL,
+    Register16::SP,
+];
+const STACK_REGISTER_PAIRS: [Register16; 4] = [
+    Register16::BC,
+    Register16::DE,
+    Register16::HL,
+    Register16::AF,
+];
+
+const INDIRECT_TARGETS: [IndirectTarget; 4] = [
+    IndirectTarget::BC,
+    IndirectTarget::DE,
+    IndirectTarget::HLIncrement,
+    IndirectTarget::HLDecrement,
+];
+
+const CONDITIONS: [JumpTest; 4] = [
+    JumpTest::NotZero,
+    JumpTest::Zero,
+    JumpTest::NotCarry,
+    JumpTest::Carry,
+];
+
+const ALU_OPERATIONS: [AluOperation; 8] = [
+    AluOperation::Add,
+    AluOperation::Adc,
+    AluOperation::Sub,
+    AluOperation::Sbc,
+    AluOperation::And,
+    AluOperation::Xor,
+    AluOperation::Or,
+    AluOperation::Cp,
+];
+
+const SHIFT_OPERATIONS: [ShiftOperation; 8] = [
+    ShiftOperation::RotateLeftCircular,
+    ShiftOperation::RotateRightCircular,
+    ShiftOperation::RotateLeft,
+    ShiftOperation::RotateRight,
+    ShiftOperation::ShiftLeftArithmetic,
+    ShiftOperation::ShiftRightArithmetic,
+    ShiftOperation::Swap,
+    ShiftOperation::ShiftRightLogical,
+];
+
 #[derive(Debug)]
 enum Instruction {
     Nop(),
-    Inc(Target),
-    IncHl(),
-    Add(Target),
-    AddHl(),
+    Stop(),
+    Halt(),
+    Inc(Register8),
+    Dec(Register8),
+    Inc16(Register16),
+    Dec16(Register16),
+    Alu(AluOperation, Register8),
+    AluN8(AluOperation),
+    AddHl(Register16),
+    AddSp(),
+    DecimalAdjust(),
+    Complement(),
+    SetCarry(),
+    ComplementCarry(),
     Jump(JumpTest),
     JumpHl(),
-    Halt(),
-    RotateLeftCircular(Target),
-    RotateRightCircular(Target),
-    RotateLeft(Target),
-    RotateRight(Target),
+    JumpRelative(JumpTest),
+    Call(JumpTest),
+    Return(JumpTest),
+    ReturnInterrupt(),
+    RotateA(ShiftOperation),
+    Shift(ShiftOperation, Register8),
+    Bit(u8, Register8),
+    Reset(u8, Register8),
+    Set(u8, Register8),
     DisableInterrupt(),
     EnableInterrupts(),
-    Load(Target),
+    LoadN16(Register16),
+    LoadN8(Register8),
+    LoadRegister(Register8, Register8),
+    LoadIntoIndirect(IndirectTarget),
+    LoadFromIndirect(IndirectTarget),
     LoadIntoMemory(),
-    LoadN8(Target),
+    LoadFromMemory(),
+    LoadSpIntoMemory(),
     LoadHC(),
     LoadHA(),
+    LoadHN8(),
+    LoadHAN8(),
+    LoadSpHl(),
+    LoadHlSpN8(),
+    Push(Register16),
+    Pop(Register16),
     Restart(u16),
-    Return(),
 }
 
+// Opcodes are decoded from their octal layout: x is bits 6-7, y bits 3-5 and z bits
+// 0-2, with y further split into p (bits 4-5) and q (bit 3) for register pairs.
 impl Instruction {
     fn from_byte(byte: u8, prefixed: bool) -> Option<Self> {
         if prefixed {
-            Self::from_byte_prefixed(byte)
+            Some(Self::from_byte_prefixed(byte))
         } else {
             Self::from_byte_non_prefixed(byte)
         }
     }
 
-    fn from_byte_prefixed(byte: u8) -> Option<Self> {
-        match byte {
-            0x00 => Some(Instruction::RotateLeftCircular(Target::B)),
-            0x01 => Some(Instruction::RotateLeftCircular(Target::C)),
-            0x02 => Some(Instruction::RotateLeftCircular(Target::D)),
-            0x03 => Some(Instruction::RotateLeftCircular(Target::E)),
-            0x04 => Some(Instruction::RotateLeftCircular(Target::H)),
-            0x05 => Some(Instruction::RotateLeftCircular(Target::L)),
-            0x07 => Some(Instruction::RotateLeftCircular(Target::A)),
-            0x08 => Some(Instruction::RotateRightCircular(Target::B)),
-            0x09 => Some(Instruction::RotateRightCircular(Target::C)),
-            0x0A => Some(Instruction::RotateRightCircular(Target::D)),
-            0x0B => Some(Instruction::RotateRightCircular(Target::E)),
-            0x0C => Some(Instruction::RotateRightCircular(Target::H)),
-            0x0D => Some(Instruction::RotateRightCircular(Target::L)),
-            0x0F => Some(Instruction::RotateRightCircular(Target::A)),
-            0x10 => Some(Instruction::RotateLeft(Target::B)),
-            0x11 => Some(Instruction::RotateLeft(Target::C)),
-            0x12 => Some(Instruction::RotateLeft(Target::D)),
-            0x13 => Some(Instruction::RotateLeft(Target::E)),
-            0x14 => Some(Instruction::RotateLeft(Target::H)),
-            0x15 => Some(Instruction::RotateLeft(Target::L)),
-            0x17 => Some(Instruction::RotateLeft(Target::A)),
-            0x18 => Some(Instruction::RotateRight(Target::B)),
-            0x19 => Some(Instruction::RotateRight(Target::C)),
-            0x1A => Some(Instruction::RotateRight(Target::D)),
-            0x1B => Some(Instruction::RotateRight(Target::E)),
-            0x1C => Some(Instruction::RotateRight(Target::H)),
-            0x1D => Some(Instruction::RotateRight(Target::L)),
-            0x1F => Some(Instruction::RotateRight(Target::A)),
-            _ => None,
+    fn from_byte_prefixed(byte: u8) -> Self {
+        let x = byte >> 6;
+        let y = (byte >> 3) & 0b111;
+        let register = REGISTERS[(byte & 0b111) as usize];
+        match x {
+            0 => Instruction::Shift(SHIFT_OPERATIONS[y as usize], register),
+            1 => Instruction::Bit(y, register),
+            2 => Instruction::Reset(y, register),
+            _ => Instruction::Set(y, register),
         }
     }
 
     fn from_byte_non_prefixed(byte: u8) -> Option<Self> {
-        match byte {
-            0x00 => Some(Instruction::Nop()),
-            0x03 => Some(Instruction::Inc(Target::BC)),
-            0x04 => Some(Instruction::Inc(Target::B)),
-            0x06 => Some(Instruction::LoadN8(Target::B)),
-            0x07 => Some(Instruction::RotateLeft(Target::A)),
-            0x0E => Some(Instruction::LoadN8(Target::C)),
-            0x0F => Some(Instruction::RotateRight(Target::A)),
-            0x13 => Some(Instruction::Inc(Target::DE)),
-            0x14 => Some(Instruction::Inc(Target::D)),
-            0x16 => Some(Instruction::LoadN8(Target::D)),
-            0x1E => Some(Instruction::LoadN8(Target::E)),
-            0x23 => Some(Instruction::Inc(Target::HL)),
-            0x24 => Some(Instruction::Inc(Target::H)),
-            0x26 => Some(Instruction::LoadN8(Target::H)),
-            0x2E => Some(Instruction::LoadN8(Target::L)),
-            0x31 => Some(Instruction::Load(Target::SP)),
-            0x33 => Some(Instruction::Inc(Target::SP)),
-            0x34 => Some(Instruction::IncHl()),
-            0x36 => Some(Instruction::LoadN8(Target::HL)),
-            0x3C => Some(Instruction::Inc(Target::A)),
-            0x3E => Some(Instruction::LoadN8(Target::A)),
-            0x76 => Some(Instruction::Halt()),
-            0x80 => Some(Instruction::Add(Target::B)),
-            0x81 => Some(Instruction::Add(Target::C)),
-            0x82 => Some(Instruction::Add(Target::D)),
-            0x83 => Some(Instruction::Add(Target::E)),
-            0x84 => Some(Instruction::Add(Target::H)),
-            0x85 => Some(Instruction::Add(Target::L)),
-            0x86 => Some(Instruction::AddHl()),
-            0x87 => Some(Instruction::Add(Target::A)),
-            0xC2 => Some(Instruction::Jump(JumpTest::NotZero)),
-            0xC3 => Some(Instruction::Jump(JumpTest::Always)),
-            0xCA => Some(Instruction::Jump(JumpTest::Zero)),
-            0xC9 => Some(Instruction::Return()),
-            0xD2 => Some(Instruction::Jump(JumpTest::NotCarry)),
-            0xDA => Some(Instruction::Jump(JumpTest::Carry)),
-            0xE0 => Some(Instruction::LoadHC()),
-            0xEA => Some(Instruction::LoadIntoMemory()),
-            0xE9 => Some(Instruction::JumpHl()),
-            0xF0 => Some(Instruction::LoadHA()),
-            0xF3 => Some(Instruction::DisableInterrupt()),
-            0xFB => Some(Instruction::EnableInterrupts()),
-            0xFF => Some(Instruction::Restart(0x38)),
-            _ => None,
-        }
+        let x = byte >> 6;
+        let y = ((byte >> 3) & 0b111) as usize;
+        let z = (byte & 0b111) as usize;
+        let p = y >> 1;
+        let q = y & 1;
+
+        let instruction = match (x, z) {
+            (0, 0) => match y {
+                0 => Instruction::Nop(),
+                1 => Instruction::LoadSpIntoMemory(),
+                2 => Instruction::Stop(),
+                3 => Instruction::JumpRelative(JumpTest::Always),
+                _ => Instruction::JumpRelative(CONDITIONS[y - 4]),
+            },
+            (0, 1) if q == 0 => Instruction::LoadN16(REGISTER_PAIRS[p]),
+            (0, 1) => Instruction::AddHl(REGISTER_PAIRS[p]),
+            (0, 2) if q == 0 => Instruction::LoadIntoIndirect(INDIRECT_TARGETS[p]),
+            (0, 2) => Instruction::LoadFromIndirect(INDIRECT_TARGETS[p]),
+            (0, 3) if q == 0 => Instruction::Inc16(REGISTER_PAIRS[p]),
+            (0, 3) => Instruction::Dec16(REGISTER_PAIRS[p]),
+            (0, 4) => Instruction::Inc(REGISTERS[y]),
+            (0, 5) => Instruction::Dec(REGISTERS[y]),
+            (0, 6) => Instruction::LoadN8(REGISTERS[y]),
+            (0, _) => match y {
+                0..=3 => Instruction::RotateA(SHIFT_OPERATIONS[y]),
+                4 => Instruction::DecimalAdjust(),
+                5 => Instruction::Complement(),
+                6 => Instruction::SetCarry(),
+                _ => Instruction::ComplementCarry(),
+            },
+            // LD (HL),(HL) is where HALT sits.
+            (1, 6) if y == 6 => Instruction::Halt(),
+            (1, _) => Instruction::LoadRegister(REGISTERS[y], REGISTERS[z]),
+            (2, _) => Instruction::Alu(ALU_OPERATIONS[y], REGISTERS[z]),
+            (_, 0) => match y {
+                0..=3 => Instruction::Return(CONDITIONS[y]),
+                4 => Instruction::LoadHN8(),
+                5 => Instruction::AddSp(),
+                6 => Instruction::LoadHAN8(),
+                _ => Instruction::LoadHlSpN8(),
+            },
+            (_, 1) if q == 0 => Instruction::Pop(STACK_REGISTER_PAIRS[p]),
+            (_, 1) => match p {
+                0 => Instruction::Return(JumpTest::Always),
+                1 => Instruction::ReturnInterrupt(),
+                2 => Instruction::JumpHl(),
+                _ => Instruction::LoadSpHl(),
+            },
+            (_, 2) => match y {
+                0..=3 => Instruction::Jump(CONDITIONS[y]),
+                4 => Instruction::LoadHC(),
+                5 => Instruction::LoadIntoMemory(),
+                6 => Instruction::LoadHA(),
+                _ => Instruction::LoadFromMemory(),
+            },
+            // 0xCB is the prefix byte and never reaches the decoder as an opcode.
+            (_, 3) => match y {
+                0 => Instruction::Jump(JumpTest::Always),
+                6 => Instruction::DisableInterrupt(),
+                7 => Instruction::EnableInterrupts(),
+                _ => return None,
+            },
+            (_, 4) if y < 4 => Instruction::Call(CONDITIONS[y]),
+            (_, 4) => return None,
+            (_, 5) if q == 0 => Instruction::Push(STACK_REGISTER_PAIRS[p]),
+            (_, 5) if p == 0 => Instruction::Call(JumpTest::Always),
+            (_, 5) => return None,
+            (_, 6) => Instruction::AluN8(ALU_OPERATIONS[y]),
+            _ => Instruction::Restart(y as u16 * 8),
+        };
+        Some(instruction)
     }
 }
 
@@ -605,6 +1044,7 @@ pub struct Registers {
     f: FlagsRegister,
     h: u8,
     l: u8,
+    sp: u16,
 }
 
 impl Registers {
@@ -618,9 +1058,65 @@ impl Registers {
             f: FlagsRegister::default(),
             h: 0,
             l: 0,
+            sp: 0xFFFE,
         }
     }
 
+    fn read8(&self, mmu: &mut Mmu, register: Register8) -> u8 {
+        match register {
+            Register8::A => self.a,
+            Register8::B => self.b,
+            Register8::C => self.c,
+            Register8::D => self.d,
+            Register8::E => self.e,
+            Register8::H => self.h,
+            Register8::L => self.l,
+            Register8::HLIndirect => mmu.read_byte(self.get_hl()),
+        }
+    }
+
+    fn write8(&mut self, mmu: &mut Mmu, register: Register8, value: u8) {
+        match register {
+            Register8::A => self.a = value,
+            Register8::B => self.b = value,
+            Register8::C => self.c = value,
+            Register8::D => self.d = value,
+            Register8::E => self.e = value,
+            Register8::H => self.h = value,
+            Register8::L => self.l = value,
+            Register8::HLIndirect => mmu.set_byte(self.get_hl(), value),
+        }
+    }
+
+    fn read16(&self, register: Register16) -> u16 {
+        match register {
+            Register16::AF => self.get_af(),
+            Register16::BC => self.get_bc(),
+            Register16::DE => self.get_de(),
+            Register16::HL => self.get_hl(),
+            Register16::SP => self.sp,
+        }
+    }
+
+    fn write16(&mut self, register: Register16, value: u16) {
+        match register {
+            Register16::AF => self.set_af(value),
+            Register16::BC => self.set_bc(value),
+            Register16::DE => self.set_de(value),
+            Register16::HL => self.set_hl(value),
+            Register16::SP => self.sp = value,
+        }
+    }
+
+    fn get_af(&self) -> u16 {
+        ((self.a as u16) << 8) | u8::from(self.f) as u16
+    }
+
+    fn set_af(&mut self, value: u16) {
+        self.a = ((value & 0xFF00) >> 8) as u8;
+        self.f = FlagsRegister::from((value & 0xFF) as u8);
+    }
+
     fn get_bc(&self) -> u16 {
         ((self.b as u16) << 8) | self.c as u16
     }
@@ -653,12 +1149,29 @@ impl Registers {
 mod tests {
 
     use super::*;
+    use crate::emulator::cartridge::Cartridge;
+    use crate::emulator::mmu::BusAccess;
+    use crate::emulator::Emulator;
+    use std::path::Path;
+
+    // Builds a bus whose cartridge holds `program` at the entry point, 0x0100.
+    fn mmu_with_program(model: Model, program: &[u8]) -> Mmu {
+        let mut cartridge = Cartridge::new();
+        cartridge.rom = vec![0; 0x8000];
+        cartridge.rom[0x100..0x100 + program.len()].copy_from_slice(program);
+        let mut mmu = Mmu::new(model);
+        mmu.load_cartridge(cartridge);
+        mmu
+    }
 
     #[test]
     fn test_rotate_right_circular() {
         let mut cpu = Cpu::new();
         cpu.registers.b = 0b0000_0001;
-        cpu.execute_instruction(&mut Mmu::new(), Instruction::RotateRightCircular(Target::B));
+        cpu.execute_instruction(
+            &mut Mmu::new(Model::Dmg),
+            Instruction::Shift(ShiftOperation::RotateRightCircular, Register8::B),
+        );
         assert_eq!(cpu.registers.b, 0b1000_0000);
         assert!(!cpu.registers.f.zero);
         assert!(cpu.registers.f.carry);
@@ -668,7 +1181,10 @@ mod tests {
     fn test_rotate_left_circular() {
         let mut cpu = Cpu::new();
         cpu.registers.b = 0b1000_0000;
-        cpu.execute_instruction(&mut Mmu::new(), Instruction::RotateLeftCircular(Target::B));
+        cpu.execute_instruction(
+            &mut Mmu::new(Model::Dmg),
+            Instruction::Shift(ShiftOperation::RotateLeftCircular, Register8::B),
+        );
         assert_eq!(cpu.registers.b, 0b0000_0001);
         assert!(!cpu.registers.f.zero);
         assert!(cpu.registers.f.carry);
@@ -678,7 +1194,10 @@ mod tests {
     fn test_rotate_right() {
         let mut cpu = Cpu::new();
         cpu.registers.b = 0b0000_0001;
-        cpu.execute_instruction(&mut Mmu::new(), Instruction::RotateRight(Target::B));
+        cpu.execute_instruction(
+            &mut Mmu::new(Model::Dmg),
+            Instruction::Shift(ShiftOperation::RotateRight, Register8::B),
+        );
         assert_eq!(cpu.registers.b, 0b0000_0000);
         assert!(cpu.registers.f.zero);
         assert!(cpu.registers.f.carry);
@@ -688,9 +1207,621 @@ mod tests {
     fn test_rotate_left() {
         let mut cpu = Cpu::new();
         cpu.registers.b = 0b1000_0000;
-        cpu.execute_instruction(&mut Mmu::new(), Instruction::RotateLeft(Target::B));
+        cpu.execute_instruction(
+            &mut Mmu::new(Model::Dmg),
+            Instruction::Shift(ShiftOperation::RotateLeft, Register8::B),
+        );
         assert_eq!(cpu.registers.b, 0b0000_0000);
         assert!(cpu.registers.f.zero);
         assert!(cpu.registers.f.carry);
     }
+
+    #[test]
+    fn test_adc_with_carry() {
+        let mut cpu = Cpu::new();
+        cpu.registers.a = 0x0F;
+        cpu.registers.b = 0xF0;
+        cpu.registers.f.carry = true;
+        cpu.execute_instruction(
+            &mut Mmu::new(Model::Dmg),
+            Instruction::Alu(AluOperation::Adc, Register8::B),
+        );
+        assert_eq!(cpu.registers.a, 0x00);
+        assert!(cpu.registers.f.zero);
+        assert!(cpu.registers.f.half_carry);
+        assert!(cpu.registers.f.carry);
+    }
+
+    #[test]
+    fn test_sbc_with_borrow() {
+        let mut cpu = Cpu::new();
+        cpu.registers.a = 0x10;
+        cpu.registers.c = 0x0F;
+        cpu.registers.f.carry = true;
+        cpu.execute_instruction(
+            &mut Mmu::new(Model::Dmg),
+            Instruction::Alu(AluOperation::Sbc, Register8::C),
+        );
+        assert_eq!(cpu.registers.a, 0x00);
+        assert!(cpu.registers.f.zero);
+        assert!(cpu.registers.f.subtract);
+        assert!(cpu.registers.f.half_carry);
+        assert!(!cpu.registers.f.carry);
+    }
+
+    #[test]
+    fn test_decimal_adjust_after_add() {
+        let mut cpu = Cpu::new();
+        cpu.registers.a = 0x45;
+        cpu.registers.b = 0x38;
+        cpu.execute_instruction(
+            &mut Mmu::new(Model::Dmg),
+            Instruction::Alu(AluOperation::Add, Register8::B),
+        );
+        cpu.execute_instruction(&mut Mmu::new(Model::Dmg), Instruction::DecimalAdjust());
+        assert_eq!(cpu.registers.a, 0x83);
+        assert!(!cpu.registers.f.carry);
+    }
+
+    #[test]
+    fn test_push_pop_af_masks_flags() {
+        let mut cpu = Cpu::new();
+        let mut mmu = Mmu::new(Model::Dmg);
+        cpu.registers.set_bc(0x12FF);
+        cpu.execute_instruction(&mut mmu, Instruction::Push(Register16::BC));
+        cpu.execute_instruction(&mut mmu, Instruction::Pop(Register16::AF));
+        assert_eq!(cpu.registers.get_af(), 0x12F0);
+        assert_eq!(cpu.registers.sp, 0xFFFE);
+    }
+
+    #[test]
+    fn test_jump_relative_backwards() {
+        let mut cpu = Cpu::new();
+        let mut mmu = mmu_with_program(Model::Dmg, &[0x18, 0xFE]);
+        let pc = cpu.execute_instruction(&mut mmu, Instruction::JumpRelative(JumpTest::Always));
+        assert_eq!(pc, 0x100);
+        cpu.registers.f.zero = true;
+        let pc = cpu.execute_instruction(&mut mmu, Instruction::JumpRelative(JumpTest::NotZero));
+        assert_eq!(pc, 0x102);
+    }
+
+    #[test]
+    fn test_call_and_return() {
+        let mut cpu = Cpu::new();
+        let mut mmu = mmu_with_program(Model::Dmg, &[0xCD, 0x34, 0x12]);
+        cpu.pc = cpu.execute_instruction(&mut mmu, Instruction::Call(JumpTest::Always));
+        assert_eq!(cpu.pc, 0x1234);
+        assert_eq!(cpu.registers.sp, 0xFFFC);
+        let pc = cpu.execute_instruction(&mut mmu, Instruction::Return(JumpTest::Always));
+        assert_eq!(pc, 0x103);
+        assert_eq!(cpu.registers.sp, 0xFFFE);
+    }
+
+    #[test]
+    fn test_load_hl_sp_offset_flags() {
+        let mut cpu = Cpu::new();
+        let mut mmu = mmu_with_program(Model::Dmg, &[0xF8, 0x01]);
+        cpu.registers.sp = 0x00FF;
+        cpu.execute_instruction(&mut mmu, Instruction::LoadHlSpN8());
+        assert_eq!(cpu.registers.get_hl(), 0x0100);
+        assert!(cpu.registers.f.half_carry);
+        assert!(cpu.registers.f.carry);
+        assert!(!cpu.registers.f.zero);
+    }
+
+    #[test]
+    fn test_rotate_left_circular_a_sets_zero_when_prefixed() {
+        let mut cpu = Cpu::new();
+        cpu.registers.a = 0;
+        cpu.execute_instruction(
+            &mut Mmu::new(Model::Dmg),
+            Instruction::Shift(ShiftOperation::RotateLeftCircular, Register8::A),
+        );
+        assert!(cpu.registers.f.zero);
+        cpu.execute_instruction(
+            &mut Mmu::new(Model::Dmg),
+            Instruction::RotateA(ShiftOperation::RotateLeftCircular),
+        );
+        assert!(!cpu.registers.f.zero);
+    }
+
+    #[test]
+    fn test_shift_right_arithmetic_keeps_sign() {
+        let mut cpu = Cpu::new();
+        cpu.registers.d = 0b1000_0011;
+        cpu.execute_instruction(
+            &mut Mmu::new(Model::Dmg),
+            Instruction::Shift(ShiftOperation::ShiftRightArithmetic, Register8::D),
+        );
+        assert_eq!(cpu.registers.d, 0b1100_0001);
+        assert!(cpu.registers.f.carry);
+    }
+
+    #[test]
+    fn test_swap_memory_operand() {
+        let mut cpu = Cpu::new();
+        let mut mmu = Mmu::new(Model::Dmg);
+        cpu.registers.set_hl(0xC000);
+        mmu.set_byte(0xC000, 0xAB);
+        let pc = cpu.execute_instruction(
+            &mut mmu,
+            Instruction::Shift(ShiftOperation::Swap, Register8::HLIndirect),
+        );
+        assert_eq!(mmu.read_byte(0xC000), 0xBA);
+        assert_eq!(pc, 0x102);
+    }
+
+    #[test]
+    fn test_bit_set_reset_memory_operand() {
+        let mut cpu = Cpu::new();
+        let mut mmu = Mmu::new(Model::Dmg);
+        cpu.registers.set_hl(0xC000);
+        cpu.registers.f.carry = true;
+        cpu.execute_instruction(&mut mmu, Instruction::Bit(7, Register8::HLIndirect));
+        assert!(cpu.registers.f.zero);
+        assert!(cpu.registers.f.half_carry);
+        assert!(cpu.registers.f.carry);
+        cpu.execute_instruction(&mut mmu, Instruction::Set(7, Register8::HLIndirect));
+        assert_eq!(mmu.read_byte(0xC000), 0x80);
+        cpu.execute_instruction(&mut mmu, Instruction::Reset(7, Register8::HLIndirect));
+        assert_eq!(mmu.read_byte(0xC000), 0x00);
+    }
+
+    #[test]
+    fn test_decoder_leaves_only_undefined_opcodes() {
+        let undefined: Vec<u8> = (0..=0xFF)
+            .filter(|&byte| byte != PREFIXED_OPCODE)
+            .filter(|&byte| Instruction::from_byte(byte, false).is_none())
+            .collect();
+        assert_eq!(
+            undefined,
+            vec![0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD]
+        );
+    }
+
+    #[test]
+    fn test_decoder_uses_opcode_fields() {
+        assert!(matches!(
+            Instruction::from_byte(0x7E, false),
+            Some(Instruction::LoadRegister(
+                Register8::A,
+                Register8::HLIndirect
+            ))
+        ));
+        assert!(matches!(
+            Instruction::from_byte(0xF5, false),
+            Some(Instruction::Push(Register16::AF))
+        ));
+        assert!(matches!(
+            Instruction::from_byte(0x3B, false),
+            Some(Instruction::Dec16(Register16::SP))
+        ));
+        assert!(matches!(
+            Instruction::from_byte(0x9E, false),
+            Some(Instruction::Alu(AluOperation::Sbc, Register8::HLIndirect))
+        ));
+        assert!(matches!(
+            Instruction::from_byte(0xEF, false),
+            Some(Instruction::Restart(0x28))
+        ));
+        assert!(matches!(
+            Instruction::from_byte(0x3E, true),
+            Some(Instruction::Shift(
+                ShiftOperation::ShiftRightLogical,
+                Register8::HLIndirect
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_step_returns_branch_dependent_cycles() {
+        let mut cpu = Cpu::new();
+        let mut mmu = mmu_with_program(
+            Model::Dmg,
+            &[
+                0x20, 0x00, // JR NZ, +0
+                0xC4, 0x00, 0x00, // CALL NZ, 0x0000
+            ],
+        );
+        cpu.registers.f.zero = true;
+        assert_eq!(cpu.step(&mut mmu).unwrap(), 2);
+        assert_eq!(cpu.step(&mut mmu).unwrap(), 3);
+
+        cpu.pc = 0x100;
+        cpu.registers.f.zero = false;
+        assert_eq!(cpu.step(&mut mmu).unwrap(), 3);
+        assert_eq!(cpu.step(&mut mmu).unwrap(), 6);
+    }
+
+    #[test]
+    fn test_step_cycles_for_memory_operands() {
+        let mut cpu = Cpu::new();
+        let mut mmu = mmu_with_program(
+            Model::Dmg,
+            &[
+                0xCB, 0x46, // BIT 0, (HL)
+                0xCB, 0xC6, // SET 0, (HL)
+                0x7E, // LD A, (HL)
+                0x23, // INC HL
+                0xC5, // PUSH BC
+                0xC9, // RET
+            ],
+        );
+        cpu.registers.set_hl(0xC000);
+
+        let cycles: Vec<u32> = (0..6).map(|_| cpu.step(&mut mmu).unwrap()).collect();
+        assert_eq!(cycles, vec![3, 4, 2, 2, 4, 4]);
+    }
+
+    #[test]
+    fn test_step_bus_order_for_read_modify_write() {
+        let mut cpu = Cpu::new();
+        let mut mmu = mmu_with_program(
+            Model::Dmg,
+            &[
+                0x34, // INC (HL)
+                0xC5, // PUSH BC
+            ],
+        );
+        cpu.registers.set_hl(0xC000);
+        cpu.registers.set_bc(0x1234);
+        cpu.registers.sp = 0xD000;
+        mmu.set_byte(0xC000, 0x41);
+        mmu.bus_log.clear();
+        let start = mmu.cycles;
+
+        assert_eq!(cpu.step(&mut mmu).unwrap(), 3);
+        assert_eq!(cpu.step(&mut mmu).unwrap(), 4);
+        let accesses: Vec<(u64, BusAccess)> = mmu
+            .bus_log
+            .iter()
+            .map(|&(cycle, access)| (cycle - start, access))
+            .collect();
+        assert_eq!(
+            accesses,
+            vec![
+                (1, BusAccess::Read(0x100)),
+                (2, BusAccess::Read(0xC000)),
+                (3, BusAccess::Write(0xC000, 0x42)),
+                (4, BusAccess::Read(0x101)),
+                // PUSH spends its second M-cycle decrementing SP, then writes the high
+                // byte first.
+                (6, BusAccess::Write(0xCFFF, 0x12)),
+                (7, BusAccess::Write(0xCFFE, 0x34)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_interrupt_dispatch() {
+        let mut cpu = Cpu::new();
+        let mut mmu = Mmu::new(Model::Dmg);
+        cpu.ime = true;
+        mmu.interrupts.write_enable(0xFF);
+        mmu.interrupts.request(Interrupt::Timer);
+        mmu.interrupts.request(Interrupt::Serial);
+
+        assert_eq!(cpu.step(&mut mmu).unwrap(), 5);
+        assert_eq!(cpu.pc, 0x50);
+        assert_eq!(cpu.registers.sp, 0xFFFC);
+        assert!(!cpu.ime);
+        assert_eq!(mmu.read_byte(0xFFFD), 0x01);
+        assert_eq!(mmu.read_byte(0xFFFC), 0x00);
+        assert_eq!(mmu.interrupts.pending(), Some(Interrupt::Serial));
+    }
+
+    #[test]
+    fn test_interrupt_cancelled_by_ie_push() {
+        let mut cpu = Cpu::new();
+        let mut mmu = Mmu::new(Model::Dmg);
+        cpu.ime = true;
+        cpu.registers.sp = 0x0000;
+        mmu.interrupts.write_enable(0b0000_0100);
+        mmu.interrupts.request(Interrupt::Timer);
+
+        cpu.step(&mut mmu).unwrap();
+        assert_eq!(cpu.pc, 0x0000);
+        assert_eq!(mmu.interrupts.pending(), None);
+    }
+
+    #[test]
+    fn test_enable_interrupts_is_delayed_by_one_instruction() {
+        let mut cpu = Cpu::new();
+        let mut mmu = mmu_with_program(Model::Dmg, &[0xFB, 0x00]); // EI; NOP
+        mmu.interrupts.write_enable(0xFF);
+        mmu.interrupts.request(Interrupt::VBlank);
+
+        cpu.step(&mut mmu).unwrap();
+        assert!(!cpu.ime);
+        cpu.step(&mut mmu).unwrap();
+        assert!(cpu.ime);
+        assert_eq!(cpu.pc, 0x102);
+        cpu.step(&mut mmu).unwrap();
+        assert_eq!(cpu.pc, 0x40);
+    }
+
+    #[test]
+    fn test_disable_interrupts_cancels_pending_enable() {
+        let mut cpu = Cpu::new();
+        let mut mmu = mmu_with_program(Model::Dmg, &[0xFB, 0xF3]); // EI; DI
+        cpu.step(&mut mmu).unwrap();
+        cpu.step(&mut mmu).unwrap();
+        assert!(!cpu.ime);
+        assert!(!cpu.ime_pending);
+    }
+
+    #[test]
+    fn test_halt_waits_for_interrupt_with_ime_disabled() {
+        let mut cpu = Cpu::new();
+        let mut mmu = mmu_with_program(Model::Dmg, &[0x76]); // HALT
+        mmu.interrupts.write_enable(0xFF);
+
+        cpu.step(&mut mmu).unwrap();
+        assert!(cpu.halted);
+        assert_eq!(cpu.step(&mut mmu).unwrap(), 1);
+        assert!(cpu.halted);
+
+        // Waking up carries straight on with the NOP after HALT.
+        mmu.interrupts.request(Interrupt::Timer);
+        assert_eq!(cpu.step(&mut mmu).unwrap(), 1);
+        assert!(!cpu.halted);
+        assert_eq!(cpu.pc, 0x102);
+    }
+
+    #[test]
+    fn test_halt_bug_reads_next_byte_twice() {
+        let mut cpu = Cpu::new();
+        let mut mmu = mmu_with_program(Model::Dmg, &[0x76, 0x3C]); // HALT; INC A
+        mmu.interrupts.write_enable(0xFF);
+        mmu.interrupts.request(Interrupt::Timer);
+
+        cpu.step(&mut mmu).unwrap();
+        assert!(!cpu.halted);
+        cpu.step(&mut mmu).unwrap();
+        cpu.step(&mut mmu).unwrap();
+        assert_eq!(cpu.registers.a, 2);
+        assert_eq!(cpu.pc, 0x102);
+    }
+
+    #[test]
+    fn test_illegal_opcode_returns_error() {
+        let mut cpu = Cpu::new();
+        let mut mmu = mmu_with_program(Model::Dmg, &[0xDD]);
+        cpu.registers.set_bc(0x1234);
+
+        match cpu.step(&mut mmu) {
+            Err(EmulatorError::IllegalOpcode {
+                opcode,
+                pc,
+                bank,
+                registers,
+            }) => {
+                assert_eq!(opcode, 0xDD);
+                assert_eq!(pc, 0x100);
+                assert_eq!(bank, 0);
+                assert_eq!(registers.bc, 0x1234);
+            }
+            other => panic!("expected an illegal opcode error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_illegal_opcode_locks_cpu() {
+        let mut cpu = Cpu::new();
+        let mut mmu = mmu_with_program(Model::Dmg, &[0xFC]);
+        cpu.set_illegal_opcode_policy(IllegalOpcodePolicy::Lock);
+        cpu.ime = true;
+        mmu.interrupts.write_enable(0xFF);
+
+        cpu.step(&mut mmu).unwrap();
+        mmu.interrupts.request(Interrupt::VBlank);
+        assert_eq!(cpu.step(&mut mmu).unwrap(), 1);
+        assert_eq!(cpu.pc, 0x100);
+    }
+
+    #[test]
+    fn test_illegal_opcode_as_nop() {
+        let mut cpu = Cpu::new();
+        let mut mmu = mmu_with_program(Model::Dmg, &[0xD3]);
+        cpu.set_illegal_opcode_policy(IllegalOpcodePolicy::Nop);
+
+        assert_eq!(cpu.step(&mut mmu).unwrap(), 1);
+        assert_eq!(cpu.pc, 0x101);
+    }
+
+    #[test]
+    fn test_post_boot_flags_follow_header_checksum() {
+        let mut cpu = Cpu::new();
+        let mut rom = vec![0; 0x150];
+        cpu.set_post_boot_state(Model::Dmg, &rom);
+        assert_eq!(cpu.registers.get_af(), 0x0180);
+
+        rom[0x14D] = 0x5A;
+        cpu.set_post_boot_state(Model::Dmg, &rom);
+        assert_eq!(cpu.registers.get_af(), 0x01B0);
+        assert_eq!(cpu.registers.get_hl(), 0x014D);
+        assert_eq!(cpu.pc, 0x100);
+    }
+
+    #[test]
+    fn test_post_boot_cgb_compatibility_hashes_title() {
+        let mut cpu = Cpu::new();
+        let mut rom = vec![0; 0x150];
+        rom[0x14B] = 0x01;
+        rom[0x134] = 0x43;
+        cpu.set_post_boot_state(Model::Cgb, &rom);
+        assert_eq!(cpu.registers.get_bc(), 0x4300);
+        assert_eq!(cpu.registers.get_hl(), 0x991A);
+
+        rom[0x143] = 0x80;
+        cpu.set_post_boot_state(Model::Agb, &rom);
+        assert_eq!(cpu.registers.get_af(), 0x1100);
+        assert_eq!(cpu.registers.get_bc(), 0x0100);
+    }
+
+    // Runs a mooneye-test-suite ROM, which loads the registers with Fibonacci numbers
+    // once it has passed.
+    fn passes_mooneye_test(name: &str, model: Model) -> bool {
+        let path = format!("roms/mooneye-test-suite/acceptance/{}.gb", name);
+        let mut emulator = Emulator::new(model);
+        emulator.load_rom(Path::new(&path)).unwrap();
+        for _ in 0..1_000_000 {
+            emulator.cpu.step(&mut emulator.mmu).unwrap();
+            let r = &emulator.cpu.registers;
+            if [r.b, r.c, r.d, r.e, r.h, r.l] == [3, 5, 8, 13, 21, 34] {
+                return true;
+            }
+        }
+        false
+    }
+
+    #[test]
+    fn test_post_boot_state_passes_mooneye_boot_tests() {
+        let tests = [
+            ("boot_regs-dmg0", Model::Dmg0),
+            ("boot_regs-dmgABC", Model::Dmg),
+            ("boot_regs-mgb", Model::Mgb),
+            ("boot_regs-sgb", Model::Sgb),
+            ("boot_regs-sgb2", Model::Sgb2),
+            ("boot_div-dmg0", Model::Dmg0),
+            ("boot_div-dmgABCmgb", Model::Dmg),
+            ("boot_div-dmgABCmgb", Model::Mgb),
+            ("boot_div-S", Model::Sgb),
+            ("boot_div-S", Model::Sgb2),
+            ("boot_div2-S", Model::Sgb),
+            ("boot_div2-S", Model::Sgb2),
+            ("boot_hwio-dmg0", Model::Dmg0),
+            ("boot_hwio-dmgABCmgb", Model::Dmg),
+            ("boot_hwio-dmgABCmgb", Model::Mgb),
+            ("boot_hwio-S", Model::Sgb),
+            ("boot_hwio-S", Model::Sgb2),
+        ];
+        for (name, model) in tests {
+            assert!(
+                passes_mooneye_test(name, model),
+                "{} failed on {:?}",
+                name,
+                model
+            );
+        }
+    }
+
+    #[test]
+    fn test_boot_rom_hands_over_to_cartridge() {
+        let mut cpu = Cpu::new();
+        let mut mmu = mmu_with_program(Model::Dmg, &[0x3C]); // INC A
+        let mut boot_rom = vec![0x00; 0x100];
+        boot_rom[0xFC..].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x50]); // LD A,1; LDH (0x50),A
+        mmu.load_boot_rom(boot_rom);
+        cpu.start_boot_rom();
+
+        while cpu.pc != 0x101 {
+            cpu.step(&mut mmu).unwrap();
+        }
+        assert!(!mmu.boot_rom_mapped());
+        assert_eq!(cpu.registers.a, 2);
+    }
+
+    #[test]
+    fn test_stop_switches_speed_when_armed() {
+        let mut cpu = Cpu::new();
+        let mut mmu = mmu_with_program(Model::Cgb, &[0x10, 0x00]); // STOP
+        mmu.set_byte(0xFF4D, 0x01);
+
+        // The opcode fetch, then 2050 M-cycles with the CPU paused for the switch.
+        assert_eq!(cpu.step(&mut mmu).unwrap(), 2051);
+        assert!(!cpu.stopped);
+        assert_eq!(cpu.pc, 0x102);
+        assert_eq!(mmu.read_byte(0xFF4D), 0xFE);
+    }
+
+    #[test]
+    fn test_step_counts_general_vram_dma_stall() {
+        let mut cpu = Cpu::new();
+        // LD A,0x7F; LDH (0x55),A; NOP
+        let mut mmu = mmu_with_program(Model::Cgb, &[0x3E, 0x7F, 0xE0, 0x55, 0x00]);
+
+        assert_eq!(cpu.step(&mut mmu).unwrap(), 2);
+        assert_eq!(cpu.step(&mut mmu).unwrap(), 3);
+        // All 128 blocks are copied before the NOP is fetched, 8 M-cycles each.
+        assert_eq!(cpu.step(&mut mmu).unwrap(), 1 + 128 * 8);
+    }
+
+    #[test]
+    fn test_hblank_vram_dma_waits_while_halted() {
+        let mut cpu = Cpu::new();
+        let mut mmu = mmu_with_program(
+            Model::Cgb,
+            &[
+                0x3E, 0x81, // LD A, 0x81
+                0xE0, 0x55, // LDH (0x55), A
+                0x76, // HALT
+                0x00, // NOP
+                0x76, // HALT
+                0x00, // NOP
+                0x00, // NOP
+            ],
+        );
+        for offset in 0..0x20 {
+            mmu.set_byte(0xC000 + offset, 0xAA);
+        }
+        mmu.set_byte(0xFF51, 0xC0);
+        mmu.set_byte(0xFF40, 0x80);
+        mmu.interrupts.write_enable(0x01);
+        // The LCD comes on in HBlank, so wait for mode 3 to arm the transfer outside it.
+        for _ in 0..40 {
+            mmu.tick();
+        }
+
+        // Two blocks are armed, then the CPU sleeps through every HBlank of the
+        // frame until VBlank without either being copied.
+        for _ in 0..3 {
+            cpu.step(&mut mmu).unwrap();
+        }
+        assert!(cpu.halted);
+        while cpu.halted {
+            cpu.step(&mut mmu).unwrap();
+        }
+        assert!(mmu.interrupts.is_requested(Interrupt::VBlank));
+        assert_eq!(mmu.read_byte(0x8000), 0x00);
+        assert_eq!(mmu.read_byte(0xFF55), 0x01);
+
+        // Woken at the start of an HBlank by the mode 0 STAT interrupt, the CPU gets
+        // one block copied before its next access.
+        mmu.set_byte(0xFF41, 0x08);
+        mmu.interrupts.write_flag(0x00);
+        mmu.interrupts.write_enable(0x02);
+        cpu.step(&mut mmu).unwrap();
+        assert!(cpu.halted);
+        while cpu.halted {
+            cpu.step(&mut mmu).unwrap();
+        }
+        cpu.step(&mut mmu).unwrap();
+        assert_eq!(mmu.read_byte(0x800F), 0xAA);
+        assert_eq!(mmu.read_byte(0x8010), 0x00);
+        assert_eq!(mmu.read_byte(0xFF55), 0x00);
+    }
+
+    #[test]
+    fn test_stop_keeps_the_system_running_until_a_joypad_input() {
+        let mut cpu = Cpu::new();
+        let mut mmu = mmu_with_program(Model::Dmg, &[0x10, 0x00]); // STOP
+        mmu.interrupts.write_enable(0xFF);
+
+        cpu.step(&mut mmu).unwrap();
+        assert!(cpu.stopped);
+        assert_eq!(cpu.step(&mut mmu).unwrap(), 1);
+        assert!(cpu.stopped);
+
+        // Only the joypad wakes the CPU, even from interrupts that are enabled.
+        mmu.interrupts.request(Interrupt::Timer);
+        mmu.interrupts.request(Interrupt::Serial);
+        cpu.step(&mut mmu).unwrap();
+        assert!(cpu.stopped);
+
+        mmu.interrupts.write_enable(0x00);
+        mmu.interrupts.request(Interrupt::Joypad);
+        cpu.step(&mut mmu).unwrap();
+        assert!(!cpu.stopped);
+    }
 }
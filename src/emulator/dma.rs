@@ -0,0 +1,170 @@
+pub const DMA_ADDRESS: u16 = 0xFF46;
+
+const TRANSFER_LENGTH: u16 = 0xA0;
+
+// A transfer starts copying on the second M-cycle after the write to DMA.
+const START_DELAY: u8 = 1;
+
+/// The two buses the DMA can read from. The CPU sees the DMA's byte instead of
+/// memory when it accesses the bus the transfer is using.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Bus {
+    External,
+    Video,
+}
+
+impl Bus {
+    fn of(address: u16) -> Option<Bus> {
+        match address {
+            0x8000..=0x9FFF => Some(Bus::Video),
+            0xFE00..=0xFFFF => None,
+            _ => Some(Bus::External),
+        }
+    }
+}
+
+struct Request {
+    source: u16,
+    delay: u8,
+}
+
+struct Transfer {
+    source: u16,
+    position: u16,
+}
+
+/// OAM DMA. Copies 160 bytes into OAM, one per M-cycle, during which the CPU can
+/// only reliably reach HRAM and the I/O registers.
+pub struct OamDma {
+    register: u8,
+    request: Option<Request>,
+    transfer: Option<Transfer>,
+    // The byte moved by the DMA in the current M-cycle.
+    value: u8,
+}
+
+impl OamDma {
+    pub fn new() -> Self {
+        OamDma {
+            register: 0,
+            request: None,
+            transfer: None,
+            value: 0xFF,
+        }
+    }
+
+    pub fn read(&self) -> u8 {
+        self.register
+    }
+
+    /// Schedules a transfer from `value * 0x100`. A transfer already running carries
+    /// on until the new one takes over.
+    pub fn write(&mut self, value: u8) {
+        self.register = value;
+        // Sources above 0xDFFF are wired to WRAM, just like echo RAM.
+        let page = match value {
+            0xE0..=0xFF => value - 0x20,
+            _ => value,
+        };
+        self.request = Some(Request {
+            source: (page as u16) << 8,
+            delay: START_DELAY,
+        });
+    }
+
+    /// Sets DMA without starting a transfer, for the value the boot ROM leaves behind.
+    pub fn set_register(&mut self, value: u8) {
+        self.register = value;
+    }
+
+    /// Advances the DMA by one M-cycle. Returns the address to copy from and the
+    /// offset into OAM to copy to, if a byte is transferred in this cycle.
+    pub fn tick(&mut self) -> Option<(u16, u16)> {
+        if let Some(request) = &mut self.request {
+            if request.delay > 0 {
+                request.delay -= 1;
+            } else {
+                self.transfer = Some(Transfer {
+                    source: request.source,
+                    position: 0,
+                });
+                self.request = None;
+            }
+        }
+
+        let transfer = self.transfer.as_mut()?;
+        if transfer.position == TRANSFER_LENGTH {
+            // OAM stays locked for the cycle after the last byte is written.
+            self.transfer = None;
+            return None;
+        }
+        let copy = (transfer.source + transfer.position, transfer.position);
+        transfer.position += 1;
+        Some(copy)
+    }
+
+    /// Records the byte the DMA read in this cycle, which is what the CPU sees if it
+    /// reads from the same bus.
+    pub fn latch(&mut self, value: u8) {
+        self.value = value;
+    }
+
+    /// The value a CPU read of `address` returns instead of memory while a transfer
+    /// is running, or `None` if the access is not affected.
+    pub fn conflict(&self, address: u16) -> Option<u8> {
+        let transfer = self.transfer.as_ref()?;
+        match address {
+            0xFE00..=0xFEFF => Some(0xFF),
+            _ if Bus::of(address).is_some() && Bus::of(address) == Bus::of(transfer.source) => {
+                Some(self.value)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transfer_starts_after_one_cycle_and_lasts_160() {
+        let mut dma = OamDma::new();
+        dma.write(0xC1);
+        assert_eq!(dma.tick(), None);
+        assert!(dma.transfer.is_none());
+
+        assert_eq!(dma.tick(), Some((0xC100, 0)));
+        for position in 1..TRANSFER_LENGTH {
+            assert_eq!(dma.tick(), Some((0xC100 + position, position)));
+        }
+        assert!(dma.transfer.is_some());
+        assert_eq!(dma.tick(), None);
+        assert!(dma.transfer.is_none());
+    }
+
+    #[test]
+    fn test_restart_keeps_old_transfer_running_until_it_takes_over() {
+        let mut dma = OamDma::new();
+        dma.write(0xC0);
+        dma.tick();
+        dma.tick();
+        dma.write(0xFE);
+        assert_eq!(dma.tick(), Some((0xC001, 1)));
+        assert_eq!(dma.tick(), Some((0xDE00, 0)));
+        assert_eq!(dma.read(), 0xFE);
+    }
+
+    #[test]
+    fn test_cpu_conflicts_during_transfer() {
+        let mut dma = OamDma::new();
+        dma.write(0x80);
+        dma.tick();
+        dma.tick();
+        dma.latch(0x42);
+        assert_eq!(dma.conflict(0xFE00), Some(0xFF));
+        assert_eq!(dma.conflict(0x9000), Some(0x42));
+        assert_eq!(dma.conflict(0xC000), None);
+        assert_eq!(dma.conflict(0xFF80), None);
+    }
+}
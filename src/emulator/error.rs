@@ -0,0 +1,174 @@
+use std::error::Error;
+use std::fmt;
+
+/// The CPU registers at the moment an error was raised.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterSnapshot {
+    pub af: u16,
+    pub bc: u16,
+    pub de: u16,
+    pub hl: u16,
+    pub sp: u16,
+    pub pc: u16,
+}
+
+// PC is left out, as errors give it alongside the ROM bank instead.
+impl fmt::Display for RegisterSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "AF={:04X} BC={:04X} DE={:04X} HL={:04X} SP={:04X}",
+            self.af, self.bc, self.de, self.hl, self.sp
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EmulatorError {
+    /// One of the eleven opcodes the SM83 leaves undefined was fetched.
+    IllegalOpcode {
+        opcode: u8,
+        pc: u16,
+        bank: u16,
+        registers: RegisterSnapshot,
+    },
+    /// The cartridge's save file could not be written.
+    SaveFailed { path: String, reason: String },
+}
+
+impl fmt::Display for EmulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmulatorError::IllegalOpcode {
+                opcode,
+                pc,
+                bank,
+                registers,
+            } => write!(
+                f,
+                "illegal opcode {:#04X} at {:02X}:{:04X} ({})",
+                opcode, bank, pc, registers
+            ),
+            EmulatorError::SaveFailed { path, reason } => {
+                write!(f, "failed to write save file {}: {}", path, reason)
+            }
+        }
+    }
+}
+
+impl Error for EmulatorError {}
+
+/// A ROM image that cannot be loaded as a cartridge.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CartridgeError {
+    /// The file ends before the end of the cartridge header.
+    Truncated {
+        length: usize,
+    },
+    UnknownRomSize(u8),
+    UnknownRamSize(u8),
+    /// The file is smaller than the ROM size given in the header.
+    RomSizeMismatch {
+        declared: usize,
+        actual: usize,
+    },
+    /// The cartridge type code is not one of a controller that is emulated.
+    UnsupportedMapper(u8),
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CartridgeError::Truncated { length } => write!(
+                f,
+                "ROM is {} bytes, too short to hold a cartridge header",
+                length
+            ),
+            CartridgeError::UnknownRomSize(code) => {
+                write!(f, "unknown ROM size code {:#04X}", code)
+            }
+            CartridgeError::UnknownRamSize(code) => {
+                write!(f, "unknown RAM size code {:#04X}", code)
+            }
+            CartridgeError::RomSizeMismatch { declared, actual } => write!(
+                f,
+                "header declares {} bytes of ROM but the file has {}",
+                declared, actual
+            ),
+            CartridgeError::UnsupportedMapper(code) => write!(
+                f,
+                "cartridge type {:#04X} uses a memory bank controller that is not supported",
+                code
+            ),
+        }
+    }
+}
+
+impl Error for CartridgeError {}
+
+/// An IPS, UPS or BPS patch that cannot be applied to the ROM.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatchError {
+    /// The file does not start with the signature of any supported format.
+    UnknownFormat,
+    /// The patch ends partway through, or copies from outside the ROM.
+    Malformed,
+    /// The patch was made for a different ROM.
+    SourceChecksum { expected: u32, actual: u32 },
+    /// The patched ROM is not the one the patch was meant to produce.
+    TargetChecksum { expected: u32, actual: u32 },
+    /// The patch file itself is damaged.
+    PatchChecksum { expected: u32, actual: u32 },
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatchError::UnknownFormat => write!(f, "not an IPS, UPS or BPS patch"),
+            PatchError::Malformed => write!(f, "patch is truncated or corrupt"),
+            PatchError::SourceChecksum { expected, actual } => write!(
+                f,
+                "patch is for a ROM with CRC32 {:08X}, but this ROM's is {:08X}",
+                expected, actual
+            ),
+            PatchError::TargetChecksum { expected, actual } => write!(
+                f,
+                "patched ROM should have CRC32 {:08X}, but has {:08X}",
+                expected, actual
+            ),
+            PatchError::PatchChecksum { expected, actual } => write!(
+                f,
+                "patch should have CRC32 {:08X}, but has {:08X}",
+                expected, actual
+            ),
+        }
+    }
+}
+
+impl Error for PatchError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_illegal_opcode_display() {
+        let error = EmulatorError::IllegalOpcode {
+            opcode: 0xD3,
+            pc: 0x4123,
+            bank: 2,
+            registers: RegisterSnapshot {
+                af: 0x01B0,
+                bc: 0x0013,
+                de: 0x00D8,
+                hl: 0x014D,
+                sp: 0xFFFE,
+                pc: 0x4123,
+            },
+        };
+        assert_eq!(
+            error.to_string(),
+            "illegal opcode 0xD3 at 02:4123 (AF=01B0 BC=0013 DE=00D8 HL=014D SP=FFFE)"
+        );
+    }
+}
@@ -0,0 +1,146 @@
+pub const HDMA1_ADDRESS: u16 = 0xFF51;
+pub const HDMA2_ADDRESS: u16 = 0xFF52;
+pub const HDMA3_ADDRESS: u16 = 0xFF53;
+pub const HDMA4_ADDRESS: u16 = 0xFF54;
+pub const HDMA5_ADDRESS: u16 = 0xFF55;
+
+pub const BLOCK_LENGTH: u16 = 0x10;
+
+const HBLANK_MODE: u8 = 0b1000_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    // Copies every block at once while the CPU waits.
+    General,
+    // Copies one block at the start of each HBlank.
+    HBlank,
+}
+
+/// CGB VRAM DMA. Copies blocks of 16 bytes from ROM or RAM into the current VRAM
+/// bank, either all at once or one block per HBlank.
+pub struct Hdma {
+    source: u16,
+    destination: u16,
+    // Blocks left to copy, from 1 to 128.
+    blocks: u8,
+    mode: Option<Mode>,
+    // An HBlank began and its block has not been copied yet.
+    hblank_pending: bool,
+}
+
+impl Hdma {
+    pub fn new() -> Self {
+        Hdma {
+            source: 0,
+            destination: 0,
+            blocks: 0,
+            mode: None,
+            hblank_pending: false,
+        }
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            HDMA1_ADDRESS..=HDMA4_ADDRESS => 0xFF,
+            // Bit 7 is clear while an HBlank transfer is running. Once a transfer has
+            // finished the remaining length wraps around to 0x7F.
+            HDMA5_ADDRESS => {
+                let inactive = if self.mode.is_some() { 0 } else { HBLANK_MODE };
+                inactive | (self.blocks.wrapping_sub(1) & 0x7F)
+            }
+            _ => unreachable!("{:#06X} is not an HDMA register", address),
+        }
+    }
+
+    /// Writes a transfer register. `hblank` says whether the LCD is currently in
+    /// HBlank, or off, in which case an HBlank transfer copies its first block at once.
+    pub fn write(&mut self, address: u16, value: u8, hblank: bool) {
+        match address {
+            HDMA1_ADDRESS => self.source = (self.source & 0x00FF) | ((value as u16) << 8),
+            HDMA2_ADDRESS => self.source = (self.source & 0xFF00) | (value & 0xF0) as u16,
+            // The destination is always in VRAM, so only its low 13 bits are kept.
+            HDMA3_ADDRESS => {
+                self.destination = (self.destination & 0x00FF) | (((value & 0x1F) as u16) << 8)
+            }
+            HDMA4_ADDRESS => self.destination = (self.destination & 0xFF00) | (value & 0xF0) as u16,
+            HDMA5_ADDRESS => {
+                self.blocks = (value & 0x7F) + 1;
+                if self.mode == Some(Mode::HBlank) && value & HBLANK_MODE == 0 {
+                    // Cancels the transfer. The length is still replaced by the one
+                    // written, which is what reads back afterwards.
+                    self.mode = None;
+                    self.hblank_pending = false;
+                } else if value & HBLANK_MODE != 0 {
+                    self.mode = Some(Mode::HBlank);
+                    self.hblank_pending = hblank;
+                } else {
+                    self.mode = Some(Mode::General);
+                }
+            }
+            _ => unreachable!("{:#06X} is not an HDMA register", address),
+        }
+    }
+
+    pub fn hblank(&mut self) {
+        if self.mode == Some(Mode::HBlank) {
+            self.hblank_pending = true;
+        }
+    }
+
+    /// Takes the next block to copy, if one is due, returning its source address and
+    /// its destination as an address in 0x8000-0x9FFF.
+    pub fn next_block(&mut self) -> Option<(u16, u16)> {
+        match self.mode? {
+            Mode::General => {}
+            Mode::HBlank if self.hblank_pending => self.hblank_pending = false,
+            Mode::HBlank => return None,
+        }
+
+        let block = (self.source, 0x8000 | self.destination);
+        self.source = self.source.wrapping_add(BLOCK_LENGTH);
+        self.destination = (self.destination + BLOCK_LENGTH) & 0x1FFF;
+        self.blocks -= 1;
+        if self.blocks == 0 {
+            self.mode = None;
+        }
+        Some(block)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_general_transfer_copies_every_block() {
+        let mut hdma = Hdma::new();
+        hdma.write(HDMA1_ADDRESS, 0xC1, false);
+        hdma.write(HDMA2_ADDRESS, 0x2F, false);
+        hdma.write(HDMA3_ADDRESS, 0xFF, false);
+        hdma.write(HDMA4_ADDRESS, 0xF0, false);
+        hdma.write(HDMA5_ADDRESS, 0x01, false);
+
+        assert_eq!(hdma.next_block(), Some((0xC120, 0x9FF0)));
+        assert_eq!(hdma.read(HDMA5_ADDRESS), 0x00);
+        assert_eq!(hdma.next_block(), Some((0xC130, 0x8000)));
+        assert_eq!(hdma.next_block(), None);
+        assert_eq!(hdma.read(HDMA5_ADDRESS), 0xFF);
+    }
+
+    #[test]
+    fn test_hblank_transfer_waits_for_hblank_and_can_be_cancelled() {
+        let mut hdma = Hdma::new();
+        hdma.write(HDMA5_ADDRESS, 0x82, false);
+        assert_eq!(hdma.next_block(), None);
+        assert_eq!(hdma.read(HDMA5_ADDRESS), 0x02);
+
+        hdma.hblank();
+        assert!(hdma.next_block().is_some());
+        assert_eq!(hdma.next_block(), None);
+
+        hdma.write(HDMA5_ADDRESS, 0x05, false);
+        assert_eq!(hdma.read(HDMA5_ADDRESS), 0x85);
+        hdma.hblank();
+        assert_eq!(hdma.next_block(), None);
+    }
+}
@@ -0,0 +1,145 @@
+pub const IF_ADDRESS: u16 = 0xFF0F;
+pub const IE_ADDRESS: u16 = 0xFFFF;
+
+// Only the low five bits of IF are wired up; the rest always read back as 1.
+const IF_UNUSED_BITS: u8 = 0b1110_0000;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Interrupt {
+    VBlank,
+    Stat,
+    Timer,
+    Serial,
+    Joypad,
+}
+
+impl Interrupt {
+    // Ordered from highest to lowest priority.
+    const ALL: [Interrupt; 5] = [
+        Interrupt::VBlank,
+        Interrupt::Stat,
+        Interrupt::Timer,
+        Interrupt::Serial,
+        Interrupt::Joypad,
+    ];
+
+    fn mask(self) -> u8 {
+        match self {
+            Interrupt::VBlank => 0b0000_0001,
+            Interrupt::Stat => 0b0000_0010,
+            Interrupt::Timer => 0b0000_0100,
+            Interrupt::Serial => 0b0000_1000,
+            Interrupt::Joypad => 0b0001_0000,
+        }
+    }
+
+    pub fn vector(self) -> u16 {
+        match self {
+            Interrupt::VBlank => 0x40,
+            Interrupt::Stat => 0x48,
+            Interrupt::Timer => 0x50,
+            Interrupt::Serial => 0x58,
+            Interrupt::Joypad => 0x60,
+        }
+    }
+}
+
+/// Owns the interrupt flag (IF) and interrupt enable (IE) registers. Components raise
+/// interrupts through `request`, and the CPU services whatever `pending` returns.
+pub struct InterruptController {
+    flag: u8,
+    enable: u8,
+    // Requests made in the current M-cycle after a halted CPU looked for interrupts,
+    // which it only notices in the next one.
+    late: u8,
+}
+
+impl InterruptController {
+    pub fn new() -> Self {
+        InterruptController {
+            flag: 0,
+            enable: 0,
+            late: 0,
+        }
+    }
+
+    pub fn request(&mut self, interrupt: Interrupt) {
+        self.flag |= interrupt.mask();
+    }
+
+    pub fn acknowledge(&mut self, interrupt: Interrupt) {
+        self.flag &= !interrupt.mask();
+    }
+
+    pub fn is_requested(&self, interrupt: Interrupt) -> bool {
+        self.flag & interrupt.mask() != 0
+    }
+
+    /// Returns the highest priority interrupt that is both requested and enabled.
+    pub fn pending(&self) -> Option<Interrupt> {
+        let active = self.flag & self.enable;
+        Interrupt::ALL
+            .into_iter()
+            .find(|interrupt| active & interrupt.mask() != 0)
+    }
+
+    /// Whether a halted CPU wakes up at the end of this M-cycle.
+    pub fn wakes_from_halt(&self) -> bool {
+        self.flag & !self.late & self.enable != 0
+    }
+
+    /// Marks whatever was requested since IF read `earlier` as too late in the
+    /// M-cycle to wake a halted CPU.
+    pub fn mark_late(&mut self, earlier: u8) {
+        self.late |= self.flag & !earlier;
+    }
+
+    /// Starts a new M-cycle.
+    pub fn tick(&mut self) {
+        self.late = 0;
+    }
+
+    pub fn read_flag(&self) -> u8 {
+        self.flag | IF_UNUSED_BITS
+    }
+
+    pub fn write_flag(&mut self, value: u8) {
+        self.flag = value & !IF_UNUSED_BITS;
+    }
+
+    pub fn read_enable(&self) -> u8 {
+        self.enable
+    }
+
+    pub fn write_enable(&mut self, value: u8) {
+        self.enable = value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pending_respects_priority_and_enable() {
+        let mut interrupts = InterruptController::new();
+        interrupts.request(Interrupt::Joypad);
+        interrupts.request(Interrupt::Timer);
+        assert_eq!(interrupts.pending(), None);
+
+        interrupts.write_enable(0xFF);
+        assert_eq!(interrupts.pending(), Some(Interrupt::Timer));
+
+        interrupts.acknowledge(Interrupt::Timer);
+        assert_eq!(interrupts.pending(), Some(Interrupt::Joypad));
+    }
+
+    #[test]
+    fn test_flag_unused_bits_read_as_one() {
+        let mut interrupts = InterruptController::new();
+        interrupts.write_flag(0xFF);
+        assert_eq!(interrupts.read_flag(), 0xFF);
+        interrupts.write_flag(0x00);
+        assert_eq!(interrupts.read_flag(), 0xE0);
+    }
+}
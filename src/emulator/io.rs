@@ -0,0 +1,138 @@
+use crate::emulator::dma::DMA_ADDRESS;
+use crate::emulator::hdma::{HDMA1_ADDRESS, HDMA4_ADDRESS, HDMA5_ADDRESS};
+use crate::emulator::interrupts::IF_ADDRESS;
+use crate::emulator::model::Model;
+use crate::emulator::ppu::{
+    BGP_ADDRESS, LCDC_ADDRESS, LYC_ADDRESS, LY_ADDRESS, OPRI_ADDRESS, SCX_ADDRESS, SCY_ADDRESS,
+    STAT_ADDRESS, VBK_ADDRESS, WX_ADDRESS,
+};
+use crate::emulator::timer::{DIV_ADDRESS, TAC_ADDRESS, TMA_ADDRESS};
+
+/// The component that backs an I/O register. Registers whose device is not emulated
+/// yet are kept as plain storage in the `Mmu`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Device {
+    Joypad,
+    Serial,
+    Timer,
+    Interrupts,
+    Audio,
+    Ppu,
+    OamDma,
+    Speed,
+    Boot,
+    Hdma,
+    Infrared,
+    Palettes,
+    WramBank,
+    // CGB registers with no documented purpose that still hold their written bits.
+    Undocumented,
+    Unmapped,
+}
+
+/// Describes how an I/O register is wired. Bits outside `read_mask` always read back
+/// as 1, and bits outside `write_mask` keep their value when the CPU writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoRegister {
+    pub device: Device,
+    pub read_mask: u8,
+    pub write_mask: u8,
+}
+
+impl IoRegister {
+    const UNMAPPED: IoRegister = IoRegister::new(Device::Unmapped, 0x00, 0x00);
+
+    const fn new(device: Device, read_mask: u8, write_mask: u8) -> Self {
+        IoRegister {
+            device,
+            read_mask,
+            write_mask,
+        }
+    }
+
+    pub fn read(self, value: u8) -> u8 {
+        value | !self.read_mask
+    }
+
+    /// Combines a CPU write with the register's current value, keeping read-only bits.
+    pub fn merge_write(self, current: u8, value: u8) -> u8 {
+        (value & self.write_mask) | (current & !self.write_mask)
+    }
+}
+
+/// Looks up the register at `address` (0xFF00-0xFF7F). CGB registers are unmapped on
+/// the other models.
+pub fn register(address: u16, model: Model) -> IoRegister {
+    let cgb = model.is_cgb();
+    match address {
+        // No buttons are emulated yet, so the input lines always read as released.
+        0xFF00 => IoRegister::new(Device::Joypad, 0x30, 0x30),
+        0xFF01 => IoRegister::new(Device::Serial, 0xFF, 0xFF),
+        // The CGB adds a clock speed select in bit 1.
+        0xFF02 if cgb => IoRegister::new(Device::Serial, 0x83, 0x83),
+        0xFF02 => IoRegister::new(Device::Serial, 0x81, 0x81),
+        DIV_ADDRESS..=TMA_ADDRESS => IoRegister::new(Device::Timer, 0xFF, 0xFF),
+        TAC_ADDRESS => IoRegister::new(Device::Timer, 0x07, 0x07),
+        IF_ADDRESS => IoRegister::new(Device::Interrupts, 0x1F, 0x1F),
+
+        0xFF10 => IoRegister::new(Device::Audio, 0x7F, 0x7F),
+        0xFF11 | 0xFF16 => IoRegister::new(Device::Audio, 0xC0, 0xFF),
+        0xFF12 | 0xFF17 | 0xFF21 | 0xFF22 | 0xFF24 | 0xFF25 => {
+            IoRegister::new(Device::Audio, 0xFF, 0xFF)
+        }
+        0xFF13 | 0xFF18 | 0xFF1D => IoRegister::new(Device::Audio, 0x00, 0xFF),
+        0xFF14 | 0xFF19 | 0xFF1E => IoRegister::new(Device::Audio, 0x40, 0xC7),
+        0xFF1A => IoRegister::new(Device::Audio, 0x80, 0x80),
+        0xFF1B => IoRegister::new(Device::Audio, 0x00, 0xFF),
+        0xFF1C => IoRegister::new(Device::Audio, 0x60, 0x60),
+        0xFF20 => IoRegister::new(Device::Audio, 0x00, 0x3F),
+        0xFF23 => IoRegister::new(Device::Audio, 0x40, 0xC0),
+        // The low nibble reports which channels are playing and cannot be written.
+        0xFF26 => IoRegister::new(Device::Audio, 0x8F, 0x80),
+        0xFF30..=0xFF3F => IoRegister::new(Device::Audio, 0xFF, 0xFF),
+
+        // STAT bit 7 is unused and the mode and coincidence bits are read-only.
+        STAT_ADDRESS => IoRegister::new(Device::Ppu, 0x7F, 0x78),
+        LY_ADDRESS => IoRegister::new(Device::Ppu, 0xFF, 0x00),
+        LCDC_ADDRESS | SCY_ADDRESS | SCX_ADDRESS | LYC_ADDRESS | BGP_ADDRESS..=WX_ADDRESS => {
+            IoRegister::new(Device::Ppu, 0xFF, 0xFF)
+        }
+        DMA_ADDRESS => IoRegister::new(Device::OamDma, 0xFF, 0xFF),
+        0xFF4D if cgb => IoRegister::new(Device::Speed, 0x81, 0x01),
+        VBK_ADDRESS if cgb => IoRegister::new(Device::Ppu, 0x01, 0x01),
+        // BOOT reads back as 0xFF whether or not the boot ROM is still mapped.
+        0xFF50 => IoRegister::new(Device::Boot, 0x00, 0x01),
+        // The DMA source and destination are write-only.
+        HDMA1_ADDRESS..=HDMA4_ADDRESS if cgb => IoRegister::new(Device::Hdma, 0x00, 0xFF),
+        HDMA5_ADDRESS if cgb => IoRegister::new(Device::Hdma, 0xFF, 0xFF),
+        0xFF56 if cgb => IoRegister::new(Device::Infrared, 0xC3, 0xC1),
+        0xFF68 | 0xFF6A if cgb => IoRegister::new(Device::Palettes, 0xBF, 0xBF),
+        0xFF69 | 0xFF6B if cgb => IoRegister::new(Device::Palettes, 0xFF, 0xFF),
+        OPRI_ADDRESS if cgb => IoRegister::new(Device::Ppu, 0x01, 0x01),
+        0xFF70 if cgb => IoRegister::new(Device::WramBank, 0x07, 0x07),
+        0xFF72..=0xFF74 if cgb => IoRegister::new(Device::Undocumented, 0xFF, 0xFF),
+        0xFF75 if cgb => IoRegister::new(Device::Undocumented, 0x70, 0x70),
+        // PCM12 and PCM34 mirror the channel outputs and are read-only.
+        0xFF76 | 0xFF77 if cgb => IoRegister::new(Device::Audio, 0xFF, 0x00),
+        _ => IoRegister::UNMAPPED,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unused_bits_read_as_one() {
+        let nr52 = register(0xFF26, Model::Dmg);
+        assert_eq!(nr52.read(nr52.merge_write(0x01, 0x00)), 0x71);
+        assert_eq!(register(0xFF03, Model::Dmg).read(0x00), 0xFF);
+    }
+
+    #[test]
+    fn test_cgb_registers_are_unmapped_on_dmg() {
+        assert_eq!(register(0xFF70, Model::Dmg).device, Device::Unmapped);
+        assert_eq!(register(0xFF70, Model::Cgb).device, Device::WramBank);
+        assert_eq!(register(0xFF02, Model::Cgb).read(0x00), 0x7C);
+    }
+}
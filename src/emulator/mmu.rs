@@ -1,25 +1,421 @@
 use crate::emulator::cartridge::Cartridge;
+use crate::emulator::dma::{OamDma, DMA_ADDRESS};
+use crate::emulator::hdma::{Hdma, BLOCK_LENGTH};
+use crate::emulator::interrupts::{InterruptController, IE_ADDRESS};
+use crate::emulator::io::{self, Device};
+use crate::emulator::model::Model;
+use crate::emulator::ppu::Ppu;
+use crate::emulator::timer::Timer;
 
+pub const DMG_BOOT_ROM_SIZE: usize = 0x100;
+pub const CGB_BOOT_ROM_SIZE: usize = 0x900;
+
+const WRAM_BANK_SIZE: usize = 0x1000;
+
+// The CPU is stalled for this many M-cycles while a CGB speed switch settles.
+const SPEED_SWITCH_CYCLES: u32 = 2050;
+
+/// A CPU bus access, recorded in tests together with the M-cycle it completed on.
+#[cfg(test)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusAccess {
+    Read(u16),
+    Write(u16, u8),
+}
+
+/// The system bus. Every CPU access is dispatched by address to the component that
+/// owns that region of the memory map.
 pub struct Mmu {
-    pub memory: [u8; 0x10000],
+    pub cycles: u64,
+    pub interrupts: InterruptController,
+    model: Model,
+    // Mapped over the start of the cartridge until the boot ROM writes to 0xFF50.
+    boot_rom: Option<Vec<u8>>,
+    cartridge: Cartridge,
+    ppu: Ppu,
+    dma: OamDma,
+    hdma: Hdma,
+    timer: Timer,
+    // Bank 0 is fixed at 0xC000; 0xD000 shows bank 1, or the bank selected by SVBK on
+    // the CGB.
+    wram: [u8; WRAM_BANK_SIZE * 8],
+    wram_bank: usize,
+    hram: [u8; 0x7F],
+    // Backing storage for I/O registers whose device is not emulated yet.
+    io: [u8; 0x80],
+    double_speed: bool,
+    speed_switch_armed: bool,
+    // HBlank DMA does not run while the CPU is halted.
+    cpu_halted: bool,
+    #[cfg(test)]
+    pub bus_log: Vec<(u64, BusAccess)>,
 }
 
 impl Mmu {
-    pub fn new() -> Mmu {
+    pub fn new(model: Model) -> Mmu {
         Mmu {
-            memory: [0; 0x10000],
+            cycles: 0,
+            interrupts: InterruptController::new(),
+            model,
+            boot_rom: None,
+            cartridge: Cartridge::new(),
+            ppu: Ppu::new(model.is_cgb()),
+            dma: OamDma::new(),
+            hdma: Hdma::new(),
+            timer: Timer::new(),
+            wram: [0; WRAM_BANK_SIZE * 8],
+            wram_bank: 1,
+            hram: [0; 0x7F],
+            io: [0; 0x80],
+            double_speed: false,
+            speed_switch_armed: false,
+            cpu_halted: false,
+            #[cfg(test)]
+            bus_log: Vec::new(),
+        }
+    }
+
+    pub fn load_cartridge(&mut self, cartridge: Cartridge) {
+        self.cartridge = cartridge;
+    }
+
+    pub fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    pub fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    pub fn load_boot_rom(&mut self, boot_rom: Vec<u8>) {
+        self.boot_rom = Some(boot_rom);
+    }
+
+    pub fn boot_rom_mapped(&self) -> bool {
+        self.boot_rom.is_some()
+    }
+
+    /// Puts the I/O registers and divider in the state the boot ROM leaves them in
+    /// when handing over to `rom`.
+    pub fn set_post_boot_state(&mut self, rom: &[u8]) {
+        for (address, value) in self.model.post_boot_io() {
+            match address {
+                // The boot ROM never starts a transfer, so only the register is restored.
+                DMA_ADDRESS => self.dma.set_register(value),
+                // Stored as-is, as the boot ROM also leaves read-only bits set.
+                0xFF00..=0xFF7F => self.store_io(address, value),
+                _ => self.write(address, value),
+            }
+        }
+        self.timer.set_counter(self.model.post_boot_divider(rom));
+        let lcd_cycles = self.model.post_boot_lcd_cycles();
+        self.ppu
+            .set_post_boot_state(lcd_cycles, &mut self.interrupts);
+    }
+
+    /// Returns the ROM bank mapped at `address`, for use in diagnostics.
+    pub fn rom_bank_at(&self, address: u16) -> u16 {
+        match address {
+            0x0000..=0x7FFF => self.cartridge.rom_bank(address) as u16,
+            _ => 0,
+        }
+    }
+
+    /// Advances the rest of the system by one M-cycle. CPU cycles that do not touch
+    /// the bus still have to call this so the other components stay in sync.
+    pub fn tick(&mut self) {
+        self.cycles += 1;
+        self.interrupts.tick();
+        self.timer.tick(&mut self.interrupts);
+        self.cartridge.tick(self.double_speed);
+        if self.ppu.tick(self.double_speed, &mut self.interrupts) && !self.cpu_halted {
+            self.hdma.hblank();
+        }
+        if let Some((source, offset)) = self.dma.tick() {
+            let value = self.read(source);
+            self.dma.latch(value);
+            self.ppu.write_oam(0xFE00 + offset, value);
+        }
+    }
+
+    /// Tells the bus whether the CPU is halted. HBlank DMA skips the HBlanks that
+    /// begin while it is, but a CPU that wakes up partway through an HBlank still
+    /// gets that HBlank's block copied.
+    pub fn set_cpu_halted(&mut self, halted: bool) {
+        self.cpu_halted = halted;
+        if !halted && self.ppu.lcd_enabled() && self.ppu.in_hblank() {
+            self.hdma.hblank();
         }
     }
 
-    pub fn load_cartridge(&mut self, cartridge: &Cartridge) {
-        self.memory[..cartridge.rom.len()].copy_from_slice(&cartridge.rom);
+    pub fn reset_divider(&mut self) {
+        self.timer.reset_divider();
+    }
+
+    pub fn speed_switch_armed(&self) -> bool {
+        self.speed_switch_armed
+    }
+
+    /// Toggles CGB double-speed mode after KEY1 was armed and STOP executed.
+    pub fn switch_speed(&mut self) {
+        self.double_speed = !self.double_speed;
+        self.speed_switch_armed = false;
+        for _ in 0..SPEED_SWITCH_CYCLES {
+            self.tick();
+        }
     }
 
-    pub fn read_byte(&self, address: u16) -> u8 {
-        self.memory[address as usize]
+    /// Reads a byte on behalf of the CPU, taking one M-cycle.
+    pub fn read_byte(&mut self, address: u16) -> u8 {
+        self.run_vram_dma();
+        self.tick();
+        #[cfg(test)]
+        self.bus_log.push((self.cycles, BusAccess::Read(address)));
+        match self.dma.conflict(address) {
+            Some(value) => value,
+            None => self.read(address),
+        }
     }
 
+    /// Writes a byte on behalf of the CPU, taking one M-cycle.
     pub fn set_byte(&mut self, address: u16, value: u8) {
-        self.memory[address as usize] = value;
+        self.run_vram_dma();
+        self.tick();
+        #[cfg(test)]
+        self.bus_log
+            .push((self.cycles, BusAccess::Write(address, value)));
+        if self.dma.conflict(address).is_none() {
+            self.write(address, value);
+        }
+    }
+
+    fn read(&self, address: u16) -> u8 {
+        if let Some(boot_rom) = &self.boot_rom {
+            // The CGB boot ROM is split around the cartridge header at 0x0100-0x01FF.
+            let overlaid = address < 0x100 || (0x200..0x900).contains(&address);
+            if overlaid && (address as usize) < boot_rom.len() {
+                return boot_rom[address as usize];
+            }
+        }
+
+        match address {
+            0x0000..=0x7FFF | 0xA000..=0xBFFF => self.cartridge.read(address),
+            0x8000..=0x9FFF => self.ppu.read_vram(address),
+            0xC000..=0xFDFF => self.wram[self.wram_index(address)],
+            0xFE00..=0xFE9F => self.ppu.read_oam(address),
+            // The area between OAM and the I/O registers is not connected to anything.
+            0xFEA0..=0xFEFF => 0x00,
+            0xFF00..=0xFF7F => self.read_io(address),
+            0xFF80..=0xFFFE => self.hram[(address - 0xFF80) as usize],
+            IE_ADDRESS => self.interrupts.read_enable(),
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x7FFF | 0xA000..=0xBFFF => self.cartridge.write(address, value),
+            0x8000..=0x9FFF => self.ppu.write_vram(address, value),
+            0xC000..=0xFDFF => self.wram[self.wram_index(address)] = value,
+            0xFE00..=0xFE9F => self.ppu.write_oam(address, value),
+            0xFEA0..=0xFEFF => {}
+            0xFF00..=0xFF7F => self.write_io(address, value),
+            0xFF80..=0xFFFE => self.hram[(address - 0xFF80) as usize] = value,
+            IE_ADDRESS => self.interrupts.write_enable(value),
+        }
+    }
+
+    fn read_io(&self, address: u16) -> u8 {
+        let register = io::register(address, self.model);
+        register.read(self.load_io(address, register.device))
+    }
+
+    fn write_io(&mut self, address: u16, value: u8) {
+        let register = io::register(address, self.model);
+        if register.device == Device::Unmapped {
+            return;
+        }
+        let current = self.load_io(address, register.device);
+        self.store_io(address, register.merge_write(current, value));
+    }
+
+    // Reads a register from its device without applying the read mask.
+    fn load_io(&self, address: u16, device: Device) -> u8 {
+        match device {
+            Device::Timer => self.timer.read(address),
+            Device::Interrupts => self.interrupts.read_flag(),
+            Device::Ppu => self.ppu.read_register(address),
+            Device::Speed => ((self.double_speed as u8) << 7) | self.speed_switch_armed as u8,
+            Device::WramBank => self.wram_bank as u8,
+            Device::OamDma => self.dma.read(),
+            Device::Hdma => self.hdma.read(address),
+            Device::Unmapped => 0xFF,
+            _ => self.io[(address - 0xFF00) as usize],
+        }
+    }
+
+    // Writes a register to its device without applying the write mask.
+    fn store_io(&mut self, address: u16, value: u8) {
+        match io::register(address, self.model).device {
+            Device::Timer => self.timer.write(address, value),
+            Device::Interrupts => self.interrupts.write_flag(value),
+            Device::Ppu => self
+                .ppu
+                .write_register(address, value, &mut self.interrupts),
+            Device::Speed => self.speed_switch_armed = value & 0b1 != 0,
+            // Unmapping the boot ROM is one-way until the next power cycle.
+            Device::Boot if value & 0b1 != 0 => self.boot_rom = None,
+            // Bank 0 cannot be mapped at 0xD000; selecting it maps bank 1 instead.
+            Device::WramBank => self.wram_bank = ((value & 0b111) as usize).max(1),
+            Device::OamDma => self.dma.write(value),
+            Device::Hdma => {
+                let hblank = !self.ppu.lcd_enabled() || self.ppu.in_hblank();
+                self.hdma.write(address, value, hblank);
+            }
+            Device::Unmapped => {}
+            _ => self.io[(address - 0xFF00) as usize] = value,
+        }
+    }
+
+    // Copies any VRAM DMA blocks that are due before the CPU's next access, stalling
+    // it for two bytes per M-cycle, or one in double speed mode.
+    fn run_vram_dma(&mut self) {
+        while let Some((source, destination)) = self.hdma.next_block() {
+            for offset in 0..BLOCK_LENGTH {
+                let value = match source.wrapping_add(offset) {
+                    // VRAM cannot be copied onto itself.
+                    0x8000..=0x9FFF => 0xFF,
+                    address => self.read(address),
+                };
+                self.ppu.write_vram(destination + offset, value);
+            }
+            let cycles = if self.double_speed {
+                BLOCK_LENGTH
+            } else {
+                BLOCK_LENGTH / 2
+            };
+            for _ in 0..cycles {
+                self.tick();
+            }
+        }
+    }
+
+    // Echo RAM at 0xE000-0xFDFF mirrors 0xC000-0xDDFF.
+    fn wram_index(&self, address: u16) -> usize {
+        let offset = (address as usize - 0xC000) & 0x1FFF;
+        if offset < WRAM_BANK_SIZE {
+            offset
+        } else {
+            self.wram_bank * WRAM_BANK_SIZE + offset - WRAM_BANK_SIZE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cartridge_with_rom(rom: Vec<u8>) -> Cartridge {
+        let mut cartridge = Cartridge::new();
+        cartridge.rom = rom;
+        cartridge
+    }
+
+    #[test]
+    fn test_boot_rom_overlays_cartridge_until_unmapped() {
+        let mut mmu = Mmu::new(Model::Dmg);
+        let mut rom = vec![0; 0x8000];
+        rom[0x0000] = 0xC3;
+        mmu.load_cartridge(cartridge_with_rom(rom));
+        mmu.load_boot_rom(vec![0x31; DMG_BOOT_ROM_SIZE]);
+
+        assert_eq!(mmu.read_byte(0x0000), 0x31);
+        assert_eq!(mmu.read_byte(0x0100), 0x00);
+        mmu.set_byte(0xFF50, 0x01);
+        assert!(!mmu.boot_rom_mapped());
+        assert_eq!(mmu.read_byte(0x0000), 0xC3);
+    }
+
+    #[test]
+    fn test_cgb_boot_rom_skips_cartridge_header() {
+        let mut mmu = Mmu::new(Model::Cgb);
+        let mut rom = vec![0; 0x8000];
+        rom[0x0134] = 0x54;
+        mmu.load_cartridge(cartridge_with_rom(rom));
+        mmu.load_boot_rom(vec![0xAA; CGB_BOOT_ROM_SIZE]);
+
+        assert_eq!(mmu.read_byte(0x0134), 0x54);
+        assert_eq!(mmu.read_byte(0x0200), 0xAA);
+        assert_eq!(mmu.read_byte(0x08FF), 0xAA);
+    }
+
+    #[test]
+    fn test_rom_is_not_writable() {
+        let mut mmu = Mmu::new(Model::Dmg);
+        mmu.load_cartridge(cartridge_with_rom(vec![0x12; 0x8000]));
+        mmu.set_byte(0x4000, 0x34);
+        assert_eq!(mmu.read_byte(0x4000), 0x12);
+    }
+
+    #[test]
+    fn test_echo_ram_mirrors_wram() {
+        let mut mmu = Mmu::new(Model::Dmg);
+        mmu.set_byte(0xC123, 0x42);
+        assert_eq!(mmu.read_byte(0xE123), 0x42);
+        mmu.set_byte(0xFDFF, 0x24);
+        assert_eq!(mmu.read_byte(0xDDFF), 0x24);
+    }
+
+    #[test]
+    fn test_unusable_area_ignores_writes() {
+        let mut mmu = Mmu::new(Model::Dmg);
+        mmu.set_byte(0xFEA0, 0x42);
+        assert_eq!(mmu.read_byte(0xFEA0), 0x00);
+    }
+
+    #[test]
+    fn test_cgb_switches_wram_banks() {
+        let mut mmu = Mmu::new(Model::Cgb);
+        mmu.set_byte(0xD000, 0x01);
+        mmu.set_byte(0xFF70, 0x02);
+        mmu.set_byte(0xD000, 0x02);
+        assert_eq!(mmu.read_byte(0xFF70), 0xFA);
+
+        mmu.set_byte(0xFF70, 0x00);
+        assert_eq!(mmu.read_byte(0xD000), 0x01);
+        assert_eq!(mmu.read_byte(0xC000), 0x00);
+    }
+
+    #[test]
+    fn test_oam_dma_copies_from_echo_ram_and_blocks_oam() {
+        let mut mmu = Mmu::new(Model::Dmg);
+        mmu.set_byte(0xDE05, 0x42);
+        mmu.set_byte(DMA_ADDRESS, 0xFE);
+        mmu.tick();
+        assert_eq!(mmu.read_byte(0xFE00), 0xFF);
+        assert_eq!(mmu.read_byte(0xFF80), 0x00);
+
+        for _ in 0..0xA0 {
+            mmu.tick();
+        }
+        assert_eq!(mmu.read_byte(0xFE05), 0x42);
+        assert_eq!(mmu.read_byte(DMA_ADDRESS), 0xFE);
+    }
+
+    #[test]
+    fn test_general_vram_dma_stalls_for_the_block_length() {
+        let mut mmu = Mmu::new(Model::Cgb);
+        for offset in 0..0x20 {
+            mmu.set_byte(0xC000 + offset, offset as u8);
+        }
+        mmu.set_byte(0xFF51, 0xC0);
+        mmu.set_byte(0xFF52, 0x00);
+        mmu.set_byte(0xFF53, 0x80);
+        mmu.set_byte(0xFF54, 0x00);
+        mmu.set_byte(0xFF55, 0x01);
+
+        let start = mmu.cycles;
+        assert_eq!(mmu.read_byte(0xFF55), 0xFF);
+        assert_eq!(mmu.cycles - start, 17);
+        assert_eq!(mmu.read_byte(0x801F), 0x1F);
     }
 }
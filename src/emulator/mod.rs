@@ -1,45 +1,268 @@
 mod cartridge;
 mod cpu;
+mod dma;
+mod error;
 mod flags;
+mod hdma;
+mod interrupts;
+mod io;
 mod mmu;
+mod model;
 mod ppu;
+#[cfg(test)]
+mod test_support;
+mod timer;
 
-use cartridge::Cartridge;
+use cartridge::{apply_patch, Cartridge, PATCH_EXTENSIONS};
+pub use cartridge::{CartridgeHeader, RtcClock, CAMERA_IMAGE_SIZE};
 use cpu::Cpu;
+pub use cpu::IllegalOpcodePolicy;
+pub use error::EmulatorError;
 use mmu::Mmu;
-use ppu::Ppu;
+pub use model::Model;
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// How often battery-backed RAM is written out while the game keeps changing it, in
+// M-cycles: about a second.
+const SAVE_INTERVAL: u64 = 1 << 20;
+
+// How often `run` checks whether it has been asked to stop, in M-cycles: one frame.
+const STOP_CHECK_INTERVAL: u64 = 17556;
 
 pub struct Emulator {
     cpu: Cpu,
-    cartridge: Cartridge,
     mmu: Mmu,
-    ppu: ppu::Ppu,
+    model: Model,
+    rtc_clock: RtcClock,
+    patch_path: Option<PathBuf>,
+    rumble: bool,
+    on_rumble: Option<Box<dyn FnMut(bool)>>,
+    saved_at: u64,
     //     input: input::Joypad,
-    //     timer: timer::Timer,
-    //     interrupts: interrupts::InterruptController,
 }
 
 impl Emulator {
-    pub fn new() -> Emulator {
+    pub fn new(model: Model) -> Emulator {
         Emulator {
             cpu: Cpu::new(),
-            cartridge: Cartridge::new(),
-            mmu: Mmu::new(),
-            ppu: Ppu::new(),
+            mmu: Mmu::new(model),
+            model,
+            rtc_clock: RtcClock::Emulated,
+            patch_path: None,
+            rumble: false,
+            on_rumble: None,
+            saved_at: 0,
         }
     }
 
-    pub fn load_rom(&mut self, path: &str) -> Result<()> {
-        self.cartridge.load(path)?;
-        self.mmu.load_cartridge(&self.cartridge);
+    /// Maps a boot ROM dump over the cartridge. Must be called before `load_rom`,
+    /// which otherwise starts the game directly in the state the boot ROM leaves.
+    pub fn load_boot_rom(&mut self, path: &Path) -> Result<()> {
+        let boot_rom = fs::read(path)?;
+        let expected = if self.model.is_cgb() {
+            mmu::CGB_BOOT_ROM_SIZE
+        } else {
+            mmu::DMG_BOOT_ROM_SIZE
+        };
+        if boot_rom.len() != expected {
+            bail!(
+                "{:?} boot ROM must be {} bytes, got {}",
+                self.model,
+                expected,
+                boot_rom.len()
+            );
+        }
+        self.mmu.load_boot_rom(boot_rom);
         Ok(())
     }
 
-    pub fn run(&mut self) {
-        loop {
-            self.cpu.step(&mut self.mmu, &mut self.ppu);
+    /// Loads a ROM file. Battery-backed RAM is kept in a `.sav` next to it, and an
+    /// `.ips`, `.ups` or `.bps` patch next to it is applied.
+    pub fn load_rom(&mut self, path: &Path) -> Result<()> {
+        let rom = fs::read(path)?;
+        self.load_rom_bytes(rom, Some(path))
+    }
+
+    /// Loads a ROM image held in memory. `path` is where it came from, if anywhere:
+    /// the save file and patches are looked for next to it as for `load_rom`.
+    /// Without one, battery-backed RAM is lost when the emulator is dropped.
+    pub fn load_rom_bytes(&mut self, rom: Vec<u8>, path: Option<&Path>) -> Result<()> {
+        let patch_path = self
+            .patch_path
+            .clone()
+            .or_else(|| path.and_then(find_patch));
+        let rom = match patch_path {
+            Some(patch_path) => {
+                let patch = fs::read(&patch_path)?;
+                apply_patch(&rom, &patch)
+                    .with_context(|| format!("Failed to apply {}", patch_path.display()))?
+            }
+            None => rom,
+        };
+        let mut cartridge = Cartridge::from_bytes(rom, self.rtc_clock)?;
+        if let Some(path) = path {
+            cartridge.set_save_path(path.with_extension("sav"))?;
+        }
+
+        if self.mmu.boot_rom_mapped() {
+            self.cpu.start_boot_rom();
+        } else {
+            self.cpu.set_post_boot_state(self.model, &cartridge.rom);
+            self.mmu.set_post_boot_state(&cartridge.rom);
         }
+        self.mmu.load_cartridge(cartridge);
+        Ok(())
+    }
+
+    pub fn cartridge_header(&self) -> Option<&CartridgeHeader> {
+        self.mmu.cartridge().header()
+    }
+
+    /// Applies this patch to the ROM instead of any found next to it. Must be called
+    /// before `load_rom`. The ROM file itself is left as it is.
+    pub fn set_patch(&mut self, path: PathBuf) {
+        self.patch_path = Some(path);
+    }
+
+    /// Chooses what drives the cartridge's real-time clock. Must be called before
+    /// `load_rom`.
+    pub fn set_rtc_clock(&mut self, clock: RtcClock) {
+        self.rtc_clock = clock;
+    }
+
+    /// Tilts the cartridge, for those with an accelerometer. `x` and `y` are in g,
+    /// positive to the right and downwards.
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.mmu.cartridge_mut().set_tilt(x, y);
+    }
+
+    /// Shows the Game Boy Camera a picture: 128x112 pixels, one byte of grey each,
+    /// from black at 0 to white at 255.
+    pub fn set_camera_image(&mut self, image: &[u8]) -> Result<()> {
+        if image.len() != CAMERA_IMAGE_SIZE {
+            bail!(
+                "camera image must be {} bytes, got {}",
+                CAMERA_IMAGE_SIZE,
+                image.len()
+            );
+        }
+        self.mmu.cartridge_mut().set_camera_image(image);
+        Ok(())
+    }
+
+    /// Registers a callback that is told whenever a rumble cartridge turns its motor
+    /// on or off.
+    pub fn on_rumble(&mut self, callback: impl FnMut(bool) + 'static) {
+        self.on_rumble = Some(Box::new(callback));
+    }
+
+    pub fn set_illegal_opcode_policy(&mut self, policy: IllegalOpcodePolicy) {
+        self.cpu.set_illegal_opcode_policy(policy);
+    }
+
+    /// Writes the cartridge's battery-backed RAM and clock to its save file, if it
+    /// has one. `run` does this periodically; call it once more after it returns.
+    pub fn save(&mut self) -> Result<(), EmulatorError> {
+        let cartridge = self.mmu.cartridge_mut();
+        cartridge
+            .save()
+            .map_err(|error| EmulatorError::SaveFailed {
+                path: cartridge
+                    .save_path()
+                    .map(|path| path.display().to_string())
+                    .unwrap_or_default(),
+                reason: error.to_string(),
+            })?;
+        self.saved_at = self.mmu.cycles;
+        Ok(())
+    }
+
+    /// Runs the game until `stop` is set, which is checked once a frame, or until
+    /// emulation fails.
+    pub fn run(&mut self, stop: &AtomicBool) -> Result<(), EmulatorError> {
+        while !stop.load(Ordering::Relaxed) {
+            self.run_for(STOP_CHECK_INTERVAL)?;
+        }
+        Ok(())
+    }
+
+    /// Runs whole instructions until at least `cycles` M-cycles have passed.
+    pub fn run_for(&mut self, cycles: u64) -> Result<(), EmulatorError> {
+        let end = self.mmu.cycles + cycles;
+        while self.mmu.cycles < end {
+            self.cpu.step(&mut self.mmu)?;
+
+            let cartridge = self.mmu.cartridge();
+            if cartridge.save_requested()
+                || (cartridge.dirty() && self.mmu.cycles - self.saved_at >= SAVE_INTERVAL)
+            {
+                self.save()?;
+            }
+
+            let rumble = self.mmu.cartridge().rumble();
+            if rumble != self.rumble {
+                self.rumble = rumble;
+                if let Some(callback) = &mut self.on_rumble {
+                    callback(rumble);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// The first patch file named after the ROM, if there is one.
+fn find_patch(rom_path: &Path) -> Option<PathBuf> {
+    PATCH_EXTENSIONS
+        .iter()
+        .map(|extension| rom_path.with_extension(extension))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::test_support::{blank_rom, scratch_directory};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_run_stops_when_asked_and_leaves_ram_to_save() {
+        let directory = scratch_directory("run_stops_when_asked");
+        // MBC1+RAM+BATTERY with one bank of RAM.
+        let mut rom = blank_rom(0x03, 0x02);
+        rom[0x100..0x10C].copy_from_slice(&[
+            0x3E, 0x0A, // LD A, 0x0A
+            0xEA, 0x00, 0x00, // LD (0x0000), A
+            0x3E, 0x42, // LD A, 0x42
+            0xEA, 0x00, 0xA0, // LD (0xA000), A
+            0x18, 0xFE, // JR -2
+        ]);
+        let mut gameboy = Emulator::new(Model::Dmg);
+        gameboy
+            .load_rom_bytes(rom, Some(&directory.join("game.gb")))
+            .unwrap();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopper = {
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                stop.store(true, Ordering::Relaxed);
+            })
+        };
+        gameboy.run(&stop).unwrap();
+        stopper.join().unwrap();
+        gameboy.save().unwrap();
+
+        let save = fs::read(directory.join("game.sav")).unwrap();
+        assert_eq!(save.len(), 0x2000);
+        assert_eq!(save[0], 0x42);
+        fs::remove_dir_all(&directory).unwrap();
     }
 }
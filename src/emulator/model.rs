@@ -0,0 +1,105 @@
+/// The Game Boy hardware revision being emulated. Each one leaves the CPU and I/O
+/// registers in a slightly different state when its boot ROM hands over to the game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Model {
+    Dmg0,
+    #[default]
+    Dmg,
+    Mgb,
+    Sgb,
+    Sgb2,
+    Cgb,
+    Agb,
+}
+
+impl Model {
+    pub fn is_cgb(self) -> bool {
+        matches!(self, Model::Cgb | Model::Agb)
+    }
+
+    /// The internal 16-bit divider counter when execution reaches 0x0100. DIV is its
+    /// upper byte, but the lower bits matter for when the next DIV and TIMA ticks land.
+    pub fn post_boot_divider(self, rom: &[u8]) -> u16 {
+        match self {
+            Model::Dmg0 => 0x182C,
+            Model::Dmg | Model::Mgb => 0xABC8,
+            // The divider counts T-cycles, four to each M-cycle.
+            Model::Sgb | Model::Sgb2 => 0xD304u16.wrapping_add(4 * sgb_header_zero_bits(rom)),
+            Model::Cgb => 0x2674,
+            // The AGB boot ROM runs one extra instruction before handing over.
+            Model::Agb => 0x2678,
+        }
+    }
+
+    /// How many M-cycles the boot ROM has had the LCD on when it hands over at 0x0100.
+    /// The DMG's leaves it partway through line 153, with LY already back at 0.
+    pub fn post_boot_lcd_cycles(self) -> u32 {
+        match self {
+            Model::Dmg0 => 16590,
+            Model::Dmg | Model::Mgb => 17531,
+            Model::Sgb | Model::Sgb2 | Model::Cgb | Model::Agb => 16466,
+        }
+    }
+
+    /// The I/O registers the boot ROM leaves behind, excluding DIV and LCD state.
+    /// Model-specific values come last so they override the shared ones.
+    pub fn post_boot_io(self) -> impl Iterator<Item = (u16, u8)> {
+        let overrides: &[(u16, u8)] = match self {
+            // The SGB boot ROM leaves the APU powered but with every channel off. It
+            // and the CGB's deselect both rows of buttons, which the DMG's leaves
+            // selected.
+            Model::Sgb | Model::Sgb2 => &[(0xFF00, 0xFF), (0xFF26, 0xF0)],
+            Model::Cgb | Model::Agb => &[(0xFF00, 0xFF), (0xFF02, 0x7F), (0xFF46, 0x00)],
+            Model::Dmg0 | Model::Dmg | Model::Mgb => &[],
+        };
+        POST_BOOT_IO.iter().chain(overrides).copied()
+    }
+}
+
+// The SGB boot ROM sends the cartridge header to the SNES a bit at a time through
+// the joypad register, and a 0 bit takes one M-cycle longer to send than a 1.
+fn sgb_header_zero_bits(rom: &[u8]) -> u16 {
+    rom.get(0x104..0x150).map_or(0, |header| {
+        header.iter().map(|byte| byte.count_zeros() as u16).sum()
+    })
+}
+
+const POST_BOOT_IO: &[(u16, u8)] = &[
+    (0xFF00, 0xCF),
+    (0xFF01, 0x00),
+    (0xFF02, 0x7E),
+    (0xFF05, 0x00),
+    (0xFF06, 0x00),
+    (0xFF07, 0xF8),
+    (0xFF0F, 0xE1),
+    (0xFF10, 0x80),
+    (0xFF11, 0xBF),
+    (0xFF12, 0xF3),
+    (0xFF13, 0xFF),
+    (0xFF14, 0xBF),
+    (0xFF16, 0x3F),
+    (0xFF17, 0x00),
+    (0xFF18, 0xFF),
+    (0xFF19, 0xBF),
+    (0xFF1A, 0x7F),
+    (0xFF1B, 0xFF),
+    (0xFF1C, 0x9F),
+    (0xFF1D, 0xFF),
+    (0xFF1E, 0xBF),
+    (0xFF20, 0xFF),
+    (0xFF21, 0x00),
+    (0xFF22, 0x00),
+    (0xFF23, 0xBF),
+    (0xFF24, 0x77),
+    (0xFF25, 0xF3),
+    (0xFF26, 0xF1),
+    (0xFF40, 0x91),
+    (0xFF42, 0x00),
+    (0xFF43, 0x00),
+    (0xFF45, 0x00),
+    (0xFF46, 0xFF),
+    (0xFF47, 0xFC),
+    (0xFF4A, 0x00),
+    (0xFF4B, 0x00),
+    (0xFFFF, 0x00),
+];
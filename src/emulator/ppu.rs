@@ -1,7 +1,496 @@
-pub struct Ppu {}
+use crate::emulator::interrupts::{Interrupt, InterruptController};
+
+pub const LCDC_ADDRESS: u16 = 0xFF40;
+pub const STAT_ADDRESS: u16 = 0xFF41;
+pub const SCY_ADDRESS: u16 = 0xFF42;
+pub const SCX_ADDRESS: u16 = 0xFF43;
+pub const LY_ADDRESS: u16 = 0xFF44;
+pub const LYC_ADDRESS: u16 = 0xFF45;
+pub const BGP_ADDRESS: u16 = 0xFF47;
+pub const OBP0_ADDRESS: u16 = 0xFF48;
+pub const OBP1_ADDRESS: u16 = 0xFF49;
+pub const WY_ADDRESS: u16 = 0xFF4A;
+pub const WX_ADDRESS: u16 = 0xFF4B;
+pub const VBK_ADDRESS: u16 = 0xFF4F;
+pub const OPRI_ADDRESS: u16 = 0xFF6C;
+
+const VRAM_BANK_SIZE: usize = 0x2000;
+
+const LCDC_ENABLE: u8 = 0b1000_0000;
+
+// STAT bits 3-6 enable the interrupt sources; bit 2 is the LY=LYC flag and bits
+// 0-1 the mode. Bit 7 is not connected.
+const STAT_ENABLE_BITS: u8 = 0b0111_1000;
+const STAT_HBLANK_ENABLE: u8 = 0b0000_1000;
+const STAT_VBLANK_ENABLE: u8 = 0b0001_0000;
+const STAT_OAM_SCAN_ENABLE: u8 = 0b0010_0000;
+const STAT_LYC_ENABLE: u8 = 0b0100_0000;
+const STAT_COINCIDENCE: u8 = 0b0000_0100;
+const STAT_UNUSED: u8 = 0b1000_0000;
+
+const DOTS_PER_LINE: u16 = 456;
+const LINES_PER_FRAME: u8 = 154;
+const VISIBLE_LINES: u8 = 144;
+const OAM_SCAN_DOTS: u16 = 80;
+// Pixel transfer takes this long at the least; fine scrolling adds to it.
+const TRANSFER_DOTS: u16 = 172;
+// LY changes at the start of a line, but is only compared with LYC, and STAT only
+// shows the OAM scan, this many dots later.
+const LINE_START_DELAY: u16 = 4;
+// Switching the LCD on starts it this far into line 0, so the first line is short.
+const LCD_ON_DOT: u16 = 6;
+const TRANSFER_START: u16 = LINE_START_DELAY + OAM_SCAN_DOTS;
+
+/// What the PPU is doing, as reported in the low bits of STAT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Transfer = 3,
+}
+
+/// Owns video memory and the LCD registers, and steps through the modes of each
+/// line, raising the VBlank and STAT interrupts. VRAM has a second bank on the CGB,
+/// selected through VBK.
+pub struct Ppu {
+    vram: [u8; VRAM_BANK_SIZE * 2],
+    vram_bank: usize,
+    oam: [u8; 0xA0],
+    lcdc: u8,
+    // Only the interrupt enable bits; the rest of STAT is put together when read.
+    stat: u8,
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+    opri: u8,
+    cgb: bool,
+    // The line being drawn, which LY runs ahead of on line 153.
+    line: u8,
+    // Position within the current line, in dots.
+    dot: u16,
+    mode: Mode,
+    // The mode as the STAT interrupt sees it, which changes slightly before STAT
+    // does, or `None` during pixel transfer, which has no interrupt.
+    interrupt_mode: Option<Mode>,
+    // The value LYC is being compared with, or `None` while LY is changing.
+    compared_ly: Option<u8>,
+    // The LY=LYC flag, which keeps its last value while the LCD is off.
+    coincidence: bool,
+    // The line ends pixel transfer at this dot.
+    transfer_end: u16,
+    // The first line after the LCD is switched on has no OAM scan.
+    first_line: bool,
+    // The STAT interrupt is requested when this, the OR of every enabled source,
+    // goes high. While one source holds it high the others cannot raise another.
+    stat_line: bool,
+}
 
 impl Ppu {
-    pub fn new() -> Self {
-        Ppu {}
+    pub fn new(cgb: bool) -> Self {
+        Ppu {
+            vram: [0; VRAM_BANK_SIZE * 2],
+            vram_bank: 0,
+            oam: [0; 0xA0],
+            lcdc: 0,
+            stat: 0,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            bgp: 0,
+            obp0: 0,
+            obp1: 0,
+            wy: 0,
+            wx: 0,
+            opri: 0,
+            cgb,
+            line: 0,
+            dot: 0,
+            mode: Mode::HBlank,
+            interrupt_mode: None,
+            compared_ly: Some(0),
+            coincidence: true,
+            transfer_end: TRANSFER_START + TRANSFER_DOTS,
+            first_line: false,
+            stat_line: false,
+        }
+    }
+
+    /// Puts the LCD where the boot ROM leaves it: on, and `cycles` M-cycles into a
+    /// frame.
+    pub fn set_post_boot_state(&mut self, cycles: u32, interrupts: &mut InterruptController) {
+        self.lcdc |= LCDC_ENABLE;
+        self.switch_on();
+        for _ in 0..cycles {
+            self.tick(false, interrupts);
+        }
+        // Requests made while getting here are the boot ROM's business.
+        self.stat_line = self.stat_sources(self.stat);
+    }
+
+    /// Advances the LCD by one M-cycle, which is two dots in double speed mode.
+    /// Returns true if HBlank began during this cycle.
+    pub fn tick(&mut self, double_speed: bool, interrupts: &mut InterruptController) -> bool {
+        if !self.lcd_enabled() {
+            return false;
+        }
+        let dots = if double_speed { 2 } else { 4 };
+        let mut hblank_started = false;
+        // A halted CPU looks for interrupts halfway through the M-cycle, so anything
+        // requested after that only wakes it in the next one.
+        let mut requested_early = 0;
+        for dot in 0..dots {
+            if dot == dots / 2 {
+                requested_early = interrupts.read_flag();
+            }
+            hblank_started |= self.step_dot(interrupts);
+        }
+        interrupts.mark_late(requested_early);
+        hblank_started
+    }
+
+    pub fn lcd_enabled(&self) -> bool {
+        self.lcdc & LCDC_ENABLE != 0
+    }
+
+    pub fn in_hblank(&self) -> bool {
+        self.line < VISIBLE_LINES && self.mode == Mode::HBlank
+    }
+
+    // Moves on by a dot, returning whether HBlank began.
+    fn step_dot(&mut self, interrupts: &mut InterruptController) -> bool {
+        self.dot += 1;
+        if self.dot == DOTS_PER_LINE {
+            self.dot = 0;
+            self.line = (self.line + 1) % LINES_PER_FRAME;
+            self.first_line = false;
+        }
+
+        let mut hblank_started = false;
+        let visible = self.line < VISIBLE_LINES;
+        match (self.line, self.dot) {
+            // LY already went back to 0 during line 153.
+            (0, 0) => {}
+            (_, 0) => {
+                self.ly = self.line;
+                self.compared_ly = None;
+            }
+            // The OAM scan interrupt comes a little before STAT shows the scan. The
+            // first line of VBlank raises it too, though no scan follows. Line 0 is
+            // the exception: coming out of line 153, which already showed LY as 0,
+            // its interrupt waits for STAT, as gambatte's lyc153int_m2irq and
+            // lycint152_m2irq tests measure on both the DMG and the CGB.
+            (1..=VISIBLE_LINES, 2) if !self.first_line => {
+                self.interrupt_mode = Some(Mode::OamScan);
+            }
+            (VISIBLE_LINES, LINE_START_DELAY) => {
+                self.compared_ly = Some(self.ly);
+                self.mode = Mode::VBlank;
+                self.interrupt_mode = Some(Mode::VBlank);
+                interrupts.request(Interrupt::VBlank);
+            }
+            // LY shows 153 only briefly before wrapping to 0, so LYC sees both.
+            (153, LINE_START_DELAY) => {
+                self.compared_ly = Some(153);
+                self.ly = 0;
+            }
+            (153, 8) => self.compared_ly = None,
+            (153, 12) => self.compared_ly = Some(0),
+            (_, LINE_START_DELAY) => {
+                self.compared_ly = Some(self.ly);
+                if visible && !self.first_line {
+                    self.mode = Mode::OamScan;
+                    self.interrupt_mode = Some(Mode::OamScan);
+                }
+            }
+            (_, TRANSFER_START) if visible => {
+                self.mode = Mode::Transfer;
+                self.interrupt_mode = None;
+                self.transfer_end = TRANSFER_START + TRANSFER_DOTS + (self.scx & 0b111) as u16;
+            }
+            (_, dot) if visible && dot == self.transfer_end => {
+                self.mode = Mode::HBlank;
+                self.interrupt_mode = Some(Mode::HBlank);
+                hblank_started = true;
+            }
+            _ => {}
+        }
+        self.update_stat_line(interrupts);
+        hblank_started
+    }
+
+    // Whether any of the STAT interrupt sources in `enabled` is active.
+    fn stat_sources(&self, enabled: u8) -> bool {
+        let mode = match self.interrupt_mode {
+            Some(Mode::HBlank) => STAT_HBLANK_ENABLE,
+            Some(Mode::VBlank) => STAT_VBLANK_ENABLE,
+            Some(Mode::OamScan) => STAT_OAM_SCAN_ENABLE,
+            Some(Mode::Transfer) | None => 0,
+        };
+        let lyc = if self.coincidence { STAT_LYC_ENABLE } else { 0 };
+        (mode | lyc) & enabled != 0
+    }
+
+    // Re-evaluates the STAT interrupt line, requesting the interrupt if it went high.
+    // Called after every dot and after writes to the LCD registers.
+    fn update_stat_line(&mut self, interrupts: &mut InterruptController) {
+        if self.lcd_enabled() {
+            self.coincidence = self.compared_ly == Some(self.lyc);
+        }
+        let line = self.stat_sources(self.stat);
+        if line && !self.stat_line {
+            interrupts.request(Interrupt::Stat);
+        }
+        self.stat_line = line;
+    }
+
+    fn switch_on(&mut self) {
+        self.line = 0;
+        self.ly = 0;
+        self.dot = LCD_ON_DOT;
+        self.mode = Mode::HBlank;
+        self.interrupt_mode = None;
+        self.compared_ly = Some(0);
+        self.first_line = true;
+    }
+
+    // With the LCD off, LY stays at 0 and STAT reports HBlank.
+    fn switch_off(&mut self) {
+        self.line = 0;
+        self.ly = 0;
+        self.dot = 0;
+        self.mode = Mode::HBlank;
+        self.interrupt_mode = None;
+    }
+
+    fn read_stat(&self) -> u8 {
+        let coincidence = if self.coincidence {
+            STAT_COINCIDENCE
+        } else {
+            0
+        };
+        STAT_UNUSED | self.stat | coincidence | self.mode as u8
+    }
+
+    // On the DMG, writing STAT enables every source for a cycle, so a write during
+    // HBlank, VBlank or LY=LYC raises the interrupt whatever is written.
+    fn write_stat(&mut self, value: u8, interrupts: &mut InterruptController) {
+        if !self.cgb && self.lcd_enabled() {
+            self.stat = STAT_ENABLE_BITS & !STAT_OAM_SCAN_ENABLE;
+            self.update_stat_line(interrupts);
+        }
+        self.stat = value & STAT_ENABLE_BITS;
+    }
+
+    /// Reads VRAM through the 0x8000-0x9FFF window of the current bank.
+    pub fn read_vram(&self, address: u16) -> u8 {
+        self.vram[self.vram_index(address)]
+    }
+
+    pub fn write_vram(&mut self, address: u16, value: u8) {
+        let index = self.vram_index(address);
+        self.vram[index] = value;
+    }
+
+    /// Reads OAM through the 0xFE00-0xFE9F window.
+    pub fn read_oam(&self, address: u16) -> u8 {
+        self.oam[(address - 0xFE00) as usize]
+    }
+
+    pub fn write_oam(&mut self, address: u16, value: u8) {
+        self.oam[(address - 0xFE00) as usize] = value;
+    }
+
+    pub fn read_register(&self, address: u16) -> u8 {
+        match address {
+            LCDC_ADDRESS => self.lcdc,
+            STAT_ADDRESS => self.read_stat(),
+            SCY_ADDRESS => self.scy,
+            SCX_ADDRESS => self.scx,
+            LY_ADDRESS => self.ly,
+            LYC_ADDRESS => self.lyc,
+            BGP_ADDRESS => self.bgp,
+            OBP0_ADDRESS => self.obp0,
+            OBP1_ADDRESS => self.obp1,
+            WY_ADDRESS => self.wy,
+            WX_ADDRESS => self.wx,
+            VBK_ADDRESS => 0b1111_1110 | self.vram_bank as u8,
+            OPRI_ADDRESS => self.opri,
+            _ => unreachable!("{:#06X} is not a PPU register", address),
+        }
+    }
+
+    pub fn write_register(
+        &mut self,
+        address: u16,
+        value: u8,
+        interrupts: &mut InterruptController,
+    ) {
+        match address {
+            LCDC_ADDRESS => {
+                let enabled = self.lcd_enabled();
+                self.lcdc = value;
+                match (enabled, self.lcd_enabled()) {
+                    (false, true) => self.switch_on(),
+                    (true, false) => self.switch_off(),
+                    _ => {}
+                }
+            }
+            STAT_ADDRESS => self.write_stat(value, interrupts),
+            SCY_ADDRESS => self.scy = value,
+            SCX_ADDRESS => self.scx = value,
+            // LY is driven by the PPU itself and cannot be written.
+            LY_ADDRESS => {}
+            LYC_ADDRESS => self.lyc = value,
+            BGP_ADDRESS => self.bgp = value,
+            OBP0_ADDRESS => self.obp0 = value,
+            OBP1_ADDRESS => self.obp1 = value,
+            WY_ADDRESS => self.wy = value,
+            WX_ADDRESS => self.wx = value,
+            VBK_ADDRESS => self.vram_bank = (value & 0b1) as usize,
+            OPRI_ADDRESS => self.opri = value,
+            _ => unreachable!("{:#06X} is not a PPU register", address),
+        }
+        self.update_stat_line(interrupts);
+    }
+
+    fn vram_index(&self, address: u16) -> usize {
+        self.vram_bank * VRAM_BANK_SIZE + (address - 0x8000) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vram_banks_are_independent() {
+        let mut ppu = Ppu::new(true);
+        let mut interrupts = InterruptController::new();
+        ppu.write_vram(0x8000, 0x11);
+        ppu.write_register(VBK_ADDRESS, 0x01, &mut interrupts);
+        ppu.write_vram(0x8000, 0x22);
+        assert_eq!(ppu.read_register(VBK_ADDRESS), 0xFF);
+        assert_eq!(ppu.read_vram(0x8000), 0x22);
+
+        ppu.write_register(VBK_ADDRESS, 0x00, &mut interrupts);
+        assert_eq!(ppu.read_vram(0x8000), 0x11);
+    }
+
+    fn switched_on(cgb: bool, interrupts: &mut InterruptController) -> Ppu {
+        let mut ppu = Ppu::new(cgb);
+        ppu.write_register(LCDC_ADDRESS, LCDC_ENABLE, interrupts);
+        ppu
+    }
+
+    #[test]
+    fn test_line_steps_through_modes() {
+        let mut interrupts = InterruptController::new();
+        let mut ppu = switched_on(false, &mut interrupts);
+        while ppu.read_register(LY_ADDRESS) != 1 {
+            ppu.tick(false, &mut interrupts);
+        }
+
+        let mut modes = Vec::new();
+        for _ in 0..DOTS_PER_LINE / 4 {
+            ppu.tick(false, &mut interrupts);
+            let mode = ppu.read_register(STAT_ADDRESS) & 0b11;
+            if modes.last() != Some(&mode) {
+                modes.push(mode);
+            }
+        }
+        assert_eq!(modes, [2, 3, 0]);
+        assert_eq!(ppu.read_register(LY_ADDRESS), 2);
+    }
+
+    #[test]
+    fn test_vblank_interrupt_and_lcd_off() {
+        let mut interrupts = InterruptController::new();
+        let mut ppu = switched_on(false, &mut interrupts);
+        let mut cycles = 0;
+        while !interrupts.is_requested(Interrupt::VBlank) {
+            ppu.tick(false, &mut interrupts);
+            cycles += 1;
+        }
+        assert_eq!(ppu.read_register(LY_ADDRESS), VISIBLE_LINES);
+        assert_eq!(ppu.read_register(STAT_ADDRESS) & 0b11, Mode::VBlank as u8);
+        let dots = VISIBLE_LINES as u32 * DOTS_PER_LINE as u32 + LINE_START_DELAY as u32;
+        assert_eq!(cycles, (dots - LCD_ON_DOT as u32).div_ceil(4));
+
+        ppu.write_register(LCDC_ADDRESS, 0, &mut interrupts);
+        assert_eq!(ppu.read_register(LY_ADDRESS), 0);
+        assert_eq!(ppu.read_register(STAT_ADDRESS) & 0b11, Mode::HBlank as u8);
+        ppu.tick(false, &mut interrupts);
+        assert_eq!(ppu.read_register(LY_ADDRESS), 0);
+    }
+
+    #[test]
+    fn test_stat_interrupt_needs_a_rising_edge() {
+        let mut interrupts = InterruptController::new();
+        let mut ppu = switched_on(true, &mut interrupts);
+        ppu.write_register(
+            STAT_ADDRESS,
+            STAT_HBLANK_ENABLE | STAT_LYC_ENABLE,
+            &mut interrupts,
+        );
+        ppu.write_register(LYC_ADDRESS, 0xFF, &mut interrupts);
+        while ppu.read_register(LY_ADDRESS) != 1 || ppu.mode != Mode::Transfer {
+            ppu.tick(false, &mut interrupts);
+        }
+        while !ppu.in_hblank() {
+            ppu.tick(false, &mut interrupts);
+        }
+        assert!(interrupts.is_requested(Interrupt::Stat));
+        interrupts.acknowledge(Interrupt::Stat);
+
+        // HBlank already holds the line high, so LY=LYC adds nothing.
+        ppu.write_register(LYC_ADDRESS, 1, &mut interrupts);
+        assert_ne!(ppu.read_register(STAT_ADDRESS) & STAT_COINCIDENCE, 0);
+        assert!(!interrupts.is_requested(Interrupt::Stat));
+
+        // Once it drops, the next source to become active raises it again.
+        while ppu.read_register(LY_ADDRESS) != 2 {
+            ppu.tick(false, &mut interrupts);
+        }
+        ppu.write_register(LYC_ADDRESS, 2, &mut interrupts);
+        ppu.tick(false, &mut interrupts);
+        assert!(interrupts.is_requested(Interrupt::Stat));
+    }
+
+    #[test]
+    fn test_oam_scan_interrupt_is_not_early_on_line_0() {
+        let mut interrupts = InterruptController::new();
+        let mut ppu = switched_on(false, &mut interrupts);
+        ppu.write_register(STAT_ADDRESS, STAT_OAM_SCAN_ENABLE, &mut interrupts);
+        ppu.write_register(LYC_ADDRESS, 0xFF, &mut interrupts);
+        while ppu.line != 153 {
+            ppu.step_dot(&mut interrupts);
+        }
+        interrupts.acknowledge(Interrupt::Stat);
+
+        // The dots into lines 0 and 1 at which the interrupt is requested and at
+        // which STAT shows the scan.
+        let mut requested = Vec::new();
+        let mut shown = Vec::new();
+        while ppu.line != 2 {
+            ppu.step_dot(&mut interrupts);
+            if interrupts.is_requested(Interrupt::Stat) {
+                interrupts.acknowledge(Interrupt::Stat);
+                requested.push((ppu.line, ppu.dot));
+            }
+            if ppu.mode == Mode::OamScan && ppu.dot < OAM_SCAN_DOTS {
+                shown.push((ppu.line, ppu.dot));
+            }
+        }
+        assert_eq!(requested, [(0, LINE_START_DELAY), (1, 2)]);
+        assert_eq!(shown.first(), Some(&(0, LINE_START_DELAY)));
+        assert!(shown.contains(&(1, LINE_START_DELAY)) && !shown.contains(&(1, 2)));
     }
 }
@@ -0,0 +1,25 @@
+//! Helpers shared by the unit tests of several modules.
+
+use crate::emulator::cartridge::header_checksum_of;
+use std::fs;
+use std::path::PathBuf;
+
+/// A blank 32 KiB ROM with this cartridge type and RAM size code in its header, and
+/// the header checksum to match.
+pub fn blank_rom(cartridge_type: u8, ram_size: u8) -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    rom[0x147] = cartridge_type;
+    rom[0x149] = ram_size;
+    rom[0x14D] = header_checksum_of(&rom);
+    rom
+}
+
+/// An empty directory for one test's files, shared neither with tests running
+/// alongside it nor with earlier runs.
+pub fn scratch_directory(test: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("emulator-{}-{}", std::process::id(), test));
+    // Left over if a test with the same process id failed before cleaning up.
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).unwrap();
+    directory
+}
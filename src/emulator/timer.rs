@@ -0,0 +1,167 @@
+use crate::emulator::interrupts::{Interrupt, InterruptController};
+
+pub const DIV_ADDRESS: u16 = 0xFF04;
+pub const TIMA_ADDRESS: u16 = 0xFF05;
+pub const TMA_ADDRESS: u16 = 0xFF06;
+pub const TAC_ADDRESS: u16 = 0xFF07;
+
+const TAC_ENABLE: u8 = 0b100;
+
+/// DIV/TIMA/TMA/TAC timer. DIV is the upper byte of a 16-bit counter that advances
+/// every T-cycle, and TIMA counts falling edges of one of the counter's bits.
+pub struct Timer {
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    // TIMA overflowed during the previous M-cycle and reads as 0 until it is reloaded.
+    overflow: bool,
+    // TIMA was reloaded from TMA during the current M-cycle.
+    reloading: bool,
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Timer {
+            counter: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            overflow: false,
+            reloading: false,
+        }
+    }
+
+    /// Sets the internal divider directly, as the boot ROM leaves it mid-count.
+    pub fn set_counter(&mut self, counter: u16) {
+        self.counter = counter;
+    }
+
+    /// Advances the timer by one M-cycle.
+    pub fn tick(&mut self, interrupts: &mut InterruptController) {
+        self.reloading = false;
+        if self.overflow {
+            self.overflow = false;
+            self.reloading = true;
+            self.tima = self.tma;
+            interrupts.request(Interrupt::Timer);
+        }
+
+        let previous = self.signal();
+        self.counter = self.counter.wrapping_add(4);
+        if previous && !self.signal() {
+            self.increment();
+        }
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            DIV_ADDRESS => (self.counter >> 8) as u8,
+            TIMA_ADDRESS => self.tima,
+            TMA_ADDRESS => self.tma,
+            TAC_ADDRESS => self.tac | 0b1111_1000,
+            _ => unreachable!("{:#06X} is not a timer register", address),
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            DIV_ADDRESS => self.reset_divider(),
+            TIMA_ADDRESS => {
+                // Writes during the reload cycle are overwritten by TMA, while writes in
+                // the cycle after an overflow cancel the pending reload.
+                if !self.reloading {
+                    self.tima = value;
+                    self.overflow = false;
+                }
+            }
+            TMA_ADDRESS => {
+                self.tma = value;
+                if self.reloading {
+                    self.tima = value;
+                }
+            }
+            TAC_ADDRESS => {
+                let previous = self.signal();
+                self.tac = value & 0b111;
+                if previous && !self.signal() {
+                    self.increment();
+                }
+            }
+            _ => unreachable!("{:#06X} is not a timer register", address),
+        }
+    }
+
+    /// Clears the internal counter, as done by writes to DIV and by STOP.
+    pub fn reset_divider(&mut self) {
+        // Resetting the counter can produce a falling edge on the selected bit.
+        let previous = self.signal();
+        self.counter = 0;
+        if previous {
+            self.increment();
+        }
+    }
+
+    fn signal(&self) -> bool {
+        let bit = match self.tac & 0b11 {
+            0b00 => 9,
+            0b01 => 3,
+            0b10 => 5,
+            _ => 7,
+        };
+        self.tac & TAC_ENABLE != 0 && self.counter & (1 << bit) != 0
+    }
+
+    fn increment(&mut self) {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = tima;
+        self.overflow = overflow;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tima_increments_at_selected_frequency() {
+        let mut timer = Timer::new();
+        let mut interrupts = InterruptController::new();
+        timer.write(TAC_ADDRESS, TAC_ENABLE | 0b01);
+        for _ in 0..4 {
+            timer.tick(&mut interrupts);
+        }
+        assert_eq!(timer.read(TIMA_ADDRESS), 1);
+    }
+
+    #[test]
+    fn test_overflow_reloads_tma_one_cycle_later() {
+        let mut timer = Timer::new();
+        let mut interrupts = InterruptController::new();
+        interrupts.write_enable(0xFF);
+        timer.write(TMA_ADDRESS, 0x42);
+        timer.write(TIMA_ADDRESS, 0xFF);
+        timer.write(TAC_ADDRESS, TAC_ENABLE | 0b01);
+        for _ in 0..4 {
+            timer.tick(&mut interrupts);
+        }
+        assert_eq!(timer.read(TIMA_ADDRESS), 0x00);
+        assert_eq!(interrupts.pending(), None);
+
+        timer.tick(&mut interrupts);
+        assert_eq!(timer.read(TIMA_ADDRESS), 0x42);
+        assert_eq!(interrupts.pending(), Some(Interrupt::Timer));
+    }
+
+    #[test]
+    fn test_div_write_resets_counter() {
+        let mut timer = Timer::new();
+        let mut interrupts = InterruptController::new();
+        for _ in 0..64 {
+            timer.tick(&mut interrupts);
+        }
+        assert_eq!(timer.read(DIV_ADDRESS), 1);
+        timer.write(DIV_ADDRESS, 0x12);
+        assert_eq!(timer.read(DIV_ADDRESS), 0);
+    }
+}
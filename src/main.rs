@@ -1,6 +1,11 @@
 use anyhow::{Context, Result};
-use clap::{Arg, Command};
+use clap::{Arg, ArgAction, Command};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
+mod archive;
 mod emulator;
 
 fn main() -> Result<()> {
@@ -8,16 +13,148 @@ fn main() -> Result<()> {
         .version("0.1.0")
         .author("OpenSauce")
         .about("A simple Game Boy emulator written in Rust")
-        .arg(Arg::new("rom").required(true).help("Path to the ROM file"))
+        .arg(
+            Arg::new("rom")
+                .required(true)
+                .help("Path to the ROM file, which may be in a .zip, .gz or .7z archive"),
+        )
+        .arg(
+            Arg::new("boot-rom")
+                .long("boot-rom")
+                .value_name("PATH")
+                .help("Run this boot ROM before the game"),
+        )
+        .arg(
+            Arg::new("patch")
+                .long("patch")
+                .value_name("PATH")
+                .help("Apply this IPS, UPS or BPS patch instead of one next to the ROM"),
+        )
+        .arg(
+            Arg::new("model")
+                .long("model")
+                .value_parser(["dmg0", "dmg", "mgb", "sgb", "sgb2", "cgb", "agb"])
+                .default_value("dmg")
+                .help("Hardware model to emulate"),
+        )
+        .arg(
+            Arg::new("illegal-opcodes")
+                .long("illegal-opcodes")
+                .value_parser(["error", "lock", "nop"])
+                .default_value("error")
+                .help("How to handle undefined opcodes"),
+        )
+        .arg(
+            Arg::new("rtc")
+                .long("rtc")
+                .value_parser(["emulated", "host"])
+                .default_value("emulated")
+                .help("Run the cartridge clock off emulated time or the host's clock"),
+        )
+        .arg(
+            Arg::new("tilt")
+                .long("tilt")
+                .num_args(2)
+                .value_names(["X", "Y"])
+                .value_parser(clap::value_parser!(f32))
+                .allow_negative_numbers(true)
+                .help("Hold tilt cartridges at this angle, in g"),
+        )
+        .arg(
+            Arg::new("camera-image")
+                .long("camera-image")
+                .value_name("PATH")
+                .help("Raw 128x112 8-bit greyscale picture for the Game Boy Camera to see"),
+        )
+        .arg(
+            Arg::new("header")
+                .long("header")
+                .action(ArgAction::SetTrue)
+                .help("Print the cartridge header and exit"),
+        )
         .get_matches();
 
     let rom_path = matches
         .get_one::<String>("rom")
         .context("ROM path is required")?;
 
-    let mut gameboy = emulator::Emulator::new();
-    gameboy.load_rom(rom_path).context("Failed to load ROM")?;
+    let model = match matches.get_one::<String>("model").map(String::as_str) {
+        Some("dmg0") => emulator::Model::Dmg0,
+        Some("mgb") => emulator::Model::Mgb,
+        Some("sgb") => emulator::Model::Sgb,
+        Some("sgb2") => emulator::Model::Sgb2,
+        Some("cgb") => emulator::Model::Cgb,
+        Some("agb") => emulator::Model::Agb,
+        _ => emulator::Model::Dmg,
+    };
+    let policy = match matches
+        .get_one::<String>("illegal-opcodes")
+        .map(String::as_str)
+    {
+        Some("lock") => emulator::IllegalOpcodePolicy::Lock,
+        Some("nop") => emulator::IllegalOpcodePolicy::Nop,
+        _ => emulator::IllegalOpcodePolicy::Error,
+    };
 
-    gameboy.run();
+    let rtc_clock = match matches.get_one::<String>("rtc").map(String::as_str) {
+        Some("host") => emulator::RtcClock::Host,
+        _ => emulator::RtcClock::Emulated,
+    };
+
+    let mut gameboy = emulator::Emulator::new(model);
+    gameboy.set_illegal_opcode_policy(policy);
+    gameboy.set_rtc_clock(rtc_clock);
+    gameboy.on_rumble(|on| eprintln!("Rumble motor {}", if on { "on" } else { "off" }));
+    if let Some(boot_rom_path) = matches.get_one::<String>("boot-rom") {
+        gameboy
+            .load_boot_rom(Path::new(boot_rom_path))
+            .context("Failed to load boot ROM")?;
+    }
+    if let Some(patch_path) = matches.get_one::<String>("patch") {
+        gameboy.set_patch(PathBuf::from(patch_path));
+    }
+    let rom_path = Path::new(rom_path);
+    if archive::is_archive(rom_path) {
+        let rom = archive::extract_rom(rom_path).context("Failed to extract ROM")?;
+        gameboy.load_rom_bytes(rom, Some(rom_path))
+    } else {
+        gameboy.load_rom(rom_path)
+    }
+    .context("Failed to load ROM")?;
+
+    if let Some(header) = gameboy.cartridge_header() {
+        if matches.get_flag("header") {
+            println!("{}", header);
+            return Ok(());
+        }
+        if !header.header_checksum_valid {
+            eprintln!("Warning: the ROM's header checksum does not match its header");
+        }
+        if !header.global_checksum_valid {
+            eprintln!("Warning: the ROM's global checksum does not match its contents");
+        }
+    }
+
+    if let Some(mut tilt) = matches.get_many::<f32>("tilt") {
+        if let (Some(&x), Some(&y)) = (tilt.next(), tilt.next()) {
+            gameboy.set_tilt(x, y);
+        }
+    }
+    if let Some(image_path) = matches.get_one::<String>("camera-image") {
+        let image = fs::read(image_path).context("Failed to read camera image")?;
+        gameboy
+            .set_camera_image(&image)
+            .context("Failed to load camera image")?;
+    }
+
+    // Ctrl-C and SIGTERM stop the emulator at the end of a frame, so that the save
+    // file is written before exiting.
+    let stop = Arc::new(AtomicBool::new(false));
+    let handler_stop = Arc::clone(&stop);
+    ctrlc::set_handler(move || handler_stop.store(true, Ordering::Relaxed))
+        .context("Failed to install the signal handler")?;
+    let result = gameboy.run(&stop);
+    gameboy.save().context("Failed to write save file")?;
+    result.context("Emulation stopped")?;
     Ok(())
 }